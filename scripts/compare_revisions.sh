@@ -0,0 +1,59 @@
+#!/usr/bin/env bash
+# Compares two revisions of the benchmark by running the same queries on both.
+#
+# Usage: scripts/compare_revisions.sh <baseline-rev> <rev> "<rates>" <query>... [-- <args>...]
+#
+# Builds both revisions in git worktrees under `target/compare/`, then runs each query at each
+# rate, in events per second, with the same arguments on both revisions. The arguments after `--`
+# are passed to the benchmark, including Timely's own after a second `--`. The output of each run
+# is kept in `target/compare/<rev>/<query>-<rate>.txt`, and the last summary timeline line of
+# each run is printed: the highest rate at which a revision's latency stays flat is its
+# throughput for that query. Passing the managed variants of a query for each backend, such as
+# `q4_mem q4_faster q4_rocksdb`, compares the revisions per backend.
+
+set -euo pipefail
+
+if [ "$#" -lt 4 ]; then
+    sed -n '4,5p' "$0" | sed 's/^# //'
+    exit 1
+fi
+
+baseline=$1
+revision=$2
+rates=$3
+shift 3
+
+queries=()
+while [ "$#" -gt 0 ] && [ "$1" != "--" ]; do
+    queries+=("$1")
+    shift
+done
+if [ "$#" -gt 0 ]; then
+    shift
+fi
+arguments=("$@")
+
+root=$(git rev-parse --show-toplevel)
+directory="$root/target/compare"
+mkdir -p "$directory"
+
+for rev in "$baseline" "$revision"; do
+    worktree="$directory/worktree-$rev"
+    if [ ! -d "$worktree" ]; then
+        git -C "$root" worktree add --detach "$worktree" "$rev"
+    fi
+    (cd "$worktree" && cargo build --release)
+    mkdir -p "$directory/$rev"
+done
+
+for query in "${queries[@]}"; do
+    for rate in $rates; do
+        for rev in "$baseline" "$revision"; do
+            output="$directory/$rev/$query-$rate.txt"
+            (cd "$directory/worktree-$rev" &&
+                cargo run --release -- --rate "$rate" --queries "$query" "${arguments[@]}") >"$output"
+            printf '%s\t%s\t%s\t%s\n' "$query" "$rate" "$rev" \
+                "$(grep summary_timeline "$output" | tail -n 1)"
+        done
+    done
+done
@@ -0,0 +1,208 @@
+//! Checkpoints of operator state, and recovery from them.
+//!
+//! The harness starts a checkpoint at an input epoch, once all input before the epoch has been
+//! sent and none after it. Each registered operator writes a snapshot of its state once its input
+//! frontier reaches the epoch, so that the snapshots of all operators reflect exactly the input
+//! before the epoch. The harness then writes a manifest recording the epoch and the input
+//! position, which marks the checkpoint as complete.
+//!
+//! Checkpoints are laid out as `<directory>/<epoch>/worker-<index>/`, holding one JSON file per
+//! operator and a `MANIFEST.json`. A run recovers from the directory of one epoch.
+//!
+//! The operators of `crate::windows` register, and so do the managed Q3 join and auction close,
+//! which find their keys through the index of each migration bin. Managed maps can't be iterated
+//! and don't expose the backends' own checkpoints, so the state of other operators isn't
+//! checkpointed, and the harness refuses to recover queries that keep such state.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use timely::scheduling::Activator;
+
+/// Records a complete checkpoint of one worker.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Input epoch the checkpoint was taken at.
+    pub epoch: usize,
+    /// Number of events the worker had generated, to resume input from.
+    pub events_so_far: usize,
+    /// Base time of the event generator, to generate the same event times on recovery.
+    pub base_time_ns: usize,
+    /// Operators that wrote a snapshot.
+    pub operators: Vec<String>,
+}
+
+struct Coordinator {
+    worker: usize,
+    /// Where to write checkpoints, if enabled.
+    directory: Option<PathBuf>,
+    /// Checkpoint to restore operators from, if recovering.
+    recover_from: Option<PathBuf>,
+    /// Names of the registered operators.
+    operators: Vec<String>,
+    /// Activators of the registered operators, to schedule them when a checkpoint starts.
+    activators: Vec<Activator>,
+    /// Epoch of the checkpoint in progress, if any.
+    pending: Option<usize>,
+    /// Operators that wrote their snapshot for the checkpoint in progress.
+    written: HashSet<String>,
+}
+
+/// Coordinates the checkpoints of the operators of one worker.
+#[derive(Clone)]
+pub struct Checkpoints(Rc<RefCell<Coordinator>>);
+
+impl Checkpoints {
+    pub fn new(worker: usize, directory: Option<PathBuf>, recover_from: Option<PathBuf>) -> Self {
+        Checkpoints(Rc::new(RefCell::new(Coordinator {
+            worker,
+            directory,
+            recover_from,
+            operators: Vec::new(),
+            activators: Vec::new(),
+            pending: None,
+            written: HashSet::new(),
+        })))
+    }
+
+    /// Checkpoints that are never taken nor restored.
+    pub fn disabled() -> Self {
+        Self::new(0, None, None)
+    }
+
+    /// Registers an operator, returning the name its snapshots are stored under.
+    ///
+    /// Operators are numbered in registration order, which is the same on every run of the same
+    /// queries, so that a recovering operator finds its own snapshot. The operator is activated
+    /// when a checkpoint starts, as its input frontier may already have reached the epoch.
+    pub fn register(&self, name: &str, activator: Activator) -> String {
+        let mut coordinator = self.0.borrow_mut();
+        let operator = format!("{}-{}", coordinator.operators.len(), name.replace(' ', "_"));
+        coordinator.operators.push(operator.clone());
+        coordinator.activators.push(activator);
+        operator
+    }
+
+    /// Whether `operator` should write its snapshot, now that its input is complete up to
+    /// `complete`.
+    pub fn is_due(&self, operator: &str, complete: usize) -> bool {
+        let coordinator = self.0.borrow();
+        coordinator.pending.map_or(false, |epoch| {
+            complete >= epoch && !coordinator.written.contains(operator)
+        })
+    }
+
+    /// Writes the snapshot of `operator` for the checkpoint in progress.
+    pub fn write<T: Serialize>(&self, operator: &str, snapshot: &T) {
+        let mut coordinator = self.0.borrow_mut();
+        let directory = coordinator
+            .worker_directory()
+            .expect("no checkpoint in progress");
+        let file = File::create(directory.join(format!("{}.json", operator)))
+            .expect("couldn't create checkpoint file");
+        serde_json::to_writer(BufWriter::new(file), snapshot).expect("couldn't write checkpoint");
+        coordinator.written.insert(operator.to_string());
+    }
+
+    /// Reads the snapshot of `operator` from the checkpoint being recovered from, if any.
+    pub fn restore<T: DeserializeOwned>(&self, operator: &str) -> Option<T> {
+        let coordinator = self.0.borrow();
+        let directory = coordinator.recovery_directory()?;
+        let file = File::open(directory.join(format!("{}.json", operator))).ok()?;
+        Some(serde_json::from_reader(BufReader::new(file)).expect("couldn't read checkpoint"))
+    }
+
+    /// Whether checkpoints are written.
+    pub fn is_enabled(&self) -> bool {
+        self.0.borrow().directory.is_some()
+    }
+
+    /// Starts a checkpoint at `epoch`.
+    pub fn start(&self, epoch: usize) {
+        let mut coordinator = self.0.borrow_mut();
+        coordinator.pending = Some(epoch);
+        coordinator.written.clear();
+        let directory = coordinator
+            .worker_directory()
+            .expect("checkpoints are disabled");
+        fs::create_dir_all(&directory).expect("couldn't create checkpoint directory");
+        for activator in coordinator.activators.iter() {
+            activator.activate();
+        }
+    }
+
+    /// Whether every registered operator wrote its snapshot for the checkpoint in progress.
+    pub fn is_written(&self) -> bool {
+        let coordinator = self.0.borrow();
+        coordinator.written.len() == coordinator.operators.len()
+    }
+
+    /// Completes the checkpoint in progress by writing its manifest.
+    pub fn finish(&self, events_so_far: usize, base_time_ns: usize) {
+        let mut coordinator = self.0.borrow_mut();
+        let directory = coordinator
+            .worker_directory()
+            .expect("no checkpoint in progress");
+        let manifest = Manifest {
+            epoch: coordinator.pending.take().unwrap(),
+            events_so_far,
+            base_time_ns,
+            operators: coordinator.operators.clone(),
+        };
+        let file = File::create(directory.join("MANIFEST.json"))
+            .expect("couldn't create checkpoint manifest");
+        serde_json::to_writer(BufWriter::new(file), &manifest).expect("couldn't write manifest");
+    }
+
+    /// The manifest of the checkpoint being recovered from, if any.
+    pub fn manifest(&self) -> Option<Manifest> {
+        let coordinator = self.0.borrow();
+        let directory = coordinator.recovery_directory()?;
+        let file = File::open(directory.join("MANIFEST.json"))
+            .expect("incomplete checkpoint, no manifest");
+        Some(serde_json::from_reader(BufReader::new(file)).expect("couldn't read manifest"))
+    }
+}
+
+impl Coordinator {
+    fn worker_directory(&self) -> Option<PathBuf> {
+        let directory = self.directory.as_ref()?;
+        let epoch = self.pending?;
+        Some(
+            directory
+                .join(epoch.to_string())
+                .join(format!("worker-{}", self.worker)),
+        )
+    }
+
+    fn recovery_directory(&self) -> Option<PathBuf> {
+        self.recover_from
+            .as_ref()
+            .map(|directory| directory.join(format!("worker-{}", self.worker)))
+    }
+}
+
+/// The latest checkpoint in `directory` that all `workers` completed, if any.
+pub fn latest_complete(directory: &Path, workers: usize) -> Option<PathBuf> {
+    fs::read_dir(directory)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let epoch = entry.file_name().to_str()?.parse::<usize>().ok()?;
+            Some((epoch, entry.path()))
+        })
+        .filter(|(_, path)| {
+            (0..workers).all(|worker| {
+                let worker_directory = path.join(format!("worker-{}", worker));
+                worker_directory.join("MANIFEST.json").exists()
+            })
+        })
+        .max_by_key(|(epoch, _)| *epoch)
+        .map(|(_, path)| path)
+}
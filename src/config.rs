@@ -71,8 +71,12 @@ impl Config {
     }
 }
 
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
 use std::f64::consts::PI;
 
+use crate::event::NEXMarkRng;
+
 // type Id = usize;
 // type Date = usize;
 
@@ -83,6 +87,15 @@ fn split_string_arg(string: String) -> Vec<String> {
     string.split(",").map(String::from).collect::<Vec<String>>()
 }
 
+fn base_url(rng: &mut SmallRng) -> String {
+    format!(
+        "https://www.nexmark.com/{}/{}/{}/item.htm?query=1",
+        rng.gen_string_with(5, '_'),
+        rng.gen_string_with(5, '_'),
+        rng.gen_string_with(5, '_')
+    )
+}
+
 // trait NEXMarkRng {
 //     fn gen_string(&mut self, usize) -> String;
 //     fn gen_price(&mut self) -> usize;
@@ -142,6 +155,11 @@ pub struct NEXMarkConfig {
     pub us_cities: Vec<String>,
     pub first_names: Vec<String>,
     pub last_names: Vec<String>,
+    pub avg_bid_byte_size: usize,
+    pub hot_channels_ratio: usize,
+    // (channel, url) pairs
+    pub hot_channels: Vec<(String, String)>,
+    pub channels: Vec<(String, String)>,
 }
 
 impl NEXMarkConfig {
@@ -182,6 +200,28 @@ impl NEXMarkConfig {
             "last-names",
             "shultz,abrams,spencer,white,bartels,walton,smith,jones,noris",
         ));
+        let avg_bid_byte_size = config.get_as_or("avg-bid-byte-size", 100);
+        let hot_channels_ratio = config.get_as_or("hot-channels-ratio", 2);
+        let channels_number = config.get_as_or("channels-number", 10_000);
+        // Channel urls come from a fixed seed, so all workers generate the same ones.
+        let mut channel_rng = SmallRng::seed_from_u64(0);
+        let hot_channels =
+            split_string_arg(config.get_or("hot-channels", "Google,Facebook,Baidu,Apple"))
+                .into_iter()
+                .map(|channel| (channel, base_url(&mut channel_rng)))
+                .collect();
+        let channels = (0..channels_number)
+            .map(|channel: i32| {
+                let mut url = base_url(&mut channel_rng);
+                if channel_rng.gen_range(0, 10) > 0 {
+                    url.push_str(&format!(
+                        "&channel_id={}",
+                        channel.reverse_bits().wrapping_abs()
+                    ));
+                }
+                (format!("channel-{}", channel), url)
+            })
+            .collect();
         let rate_shape = if config.get_or("rate-shape", "sine") == "sine" {
             RateShape::Sine
         } else {
@@ -268,6 +308,10 @@ impl NEXMarkConfig {
             us_cities: us_cities,
             first_names: first_names,
             last_names: last_names,
+            avg_bid_byte_size: avg_bid_byte_size,
+            hot_channels_ratio: hot_channels_ratio,
+            hot_channels: hot_channels,
+            channels: channels,
         }
     }
 
@@ -323,6 +367,13 @@ impl NexMarkInputTimes {
         this
     }
 
+    /// Skips the input times of the first `events_so_far` events, to resume input after them.
+    pub fn resume(mut self, events_so_far: usize) -> Self {
+        self.events_so_far = events_so_far;
+        self.make_next();
+        self
+    }
+
     fn make_next(&mut self) {
         let ts = self
             .config
@@ -6,8 +6,11 @@ use std::cmp::{max, min};
 
 use crate::config::NEXMarkConfig;
 
-trait NEXMarkRng {
+pub(crate) trait NEXMarkRng {
     fn gen_string(&mut self, _max: usize) -> String;
+    fn gen_string_with(&mut self, max: usize, special: char) -> String;
+    fn gen_exact_string(&mut self, len: usize) -> String;
+    fn gen_extra(&mut self, current_size: usize, desired_average_size: usize) -> String;
     fn gen_price(&mut self) -> usize;
 }
 
@@ -26,6 +29,47 @@ impl NEXMarkRng for SmallRng {
         String::new()
     }
 
+    fn gen_string_with(&mut self, max: usize, special: char) -> String {
+        let len = self.gen_range(MIN_STRING_LENGTH, max);
+        (0..len)
+            .map(|_| {
+                if self.gen_range(0, 13) == 0 {
+                    special
+                } else {
+                    (b'a' + self.gen_range(0, 26)) as char
+                }
+            })
+            .collect()
+    }
+
+    fn gen_exact_string(&mut self, len: usize) -> String {
+        (0..len)
+            .map(|_| {
+                if self.gen_range(0, 13) == 0 {
+                    ' '
+                } else {
+                    (b'a' + self.gen_range(0, 26)) as char
+                }
+            })
+            .collect()
+    }
+
+    /// Generates padding so that events average `desired_average_size` bytes, give or take 20%.
+    fn gen_extra(&mut self, current_size: usize, desired_average_size: usize) -> String {
+        if current_size > desired_average_size {
+            return String::new();
+        }
+        let desired_average_size = desired_average_size - current_size;
+        let delta = (desired_average_size as f32 * 0.2).round() as usize;
+        let min_size = desired_average_size - delta;
+        let jitter = if delta == 0 {
+            0
+        } else {
+            self.gen_range(0, 2 * delta)
+        };
+        self.gen_exact_string(min_size + jitter)
+    }
+
     fn gen_price(&mut self) -> usize {
         (10.0_f32.powf(self.gen::<f32>() * 6.0) * 100.0).round() as usize
     }
@@ -327,9 +371,12 @@ pub struct Bid {
     pub auction: Id,
     pub bidder: Id,
     pub price: usize,
+    pub channel: String,
+    pub url: String,
     pub date_time: Date,
+    pub extra: String,
 }
-// unsafe_abomonate!(Bid : auction, bidder, price, date_time);
+// unsafe_abomonate!(Bid : auction, bidder, price, channel, url, date_time, extra);
 
 impl Bid {
     pub fn from(event: Event) -> Option<Bid> {
@@ -350,11 +397,21 @@ impl Bid {
         } else {
             Person::next_id(id, rng, nex)
         };
+        let (channel, url) = if 0 < rng.gen_range(0, nex.hot_channels_ratio) {
+            nex.hot_channels.choose(rng).unwrap().clone()
+        } else {
+            nex.channels.choose(rng).unwrap().clone()
+        };
+        // auction, bidder, price and date_time take 8 bytes each
+        let extra = rng.gen_extra(32, nex.avg_bid_byte_size);
         Bid {
             auction: auction + nex.first_auction_id,
             bidder: bidder + nex.first_person_id,
             price: rng.gen_price(),
+            channel,
+            url,
             date_time: time,
+            extra,
         }
     }
 }
@@ -10,8 +10,13 @@ extern crate faster_rs;
 extern crate rand;
 extern crate streaming_harness;
 
+pub mod checkpoint;
 pub mod config;
 pub mod event;
+pub mod migration;
+pub mod primitives;
+pub mod state_sizes;
 pub mod tools;
+pub mod windows;
 
 pub mod queries;
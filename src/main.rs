@@ -35,16 +35,25 @@ use timely::state::backends::{
 };
 use timely::ExchangeData;
 
-use nexmark::event::Event;
-use nexmark::queries::{NexmarkInput, NexmarkTimer};
+use nexmark::checkpoint::Checkpoints;
+use nexmark::state_sizes::StateSizes;
+use nexmark::migration::{initial_owner, Control, BINS};
+use nexmark::tools::{MigrationMetrics, RecoveryMetrics};
+use nexmark::event::{Date, Event};
+use nexmark::windows::Backing;
+use nexmark::queries::{HotKeys, NexmarkInput, NexmarkTimer, Partitioner, Partitioning, RecordCounts, SideInput, TopNEmission};
 use timely::dataflow::operators::inspect::Inspect;
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use std::io::Write;
 use metrics_runtime::exporters::LogExporter;
 use metrics_runtime::observers::YamlBuilder;
 use log::Level;
 use std::fs::File;
+use std::path::PathBuf;
+use std::collections::BTreeMap;
 
 #[allow(dead_code)]
 fn verify<S: Scope, T: ExchangeData + Ord + ::std::fmt::Debug>(
@@ -96,6 +105,32 @@ fn verify<S: Scope, T: ExchangeData + Ord + ::std::fmt::Debug>(
     )
 }
 
+/// Whether `query` is one of the windowed queries, natively or on a state backend.
+fn is_windowed(query: &str) -> bool {
+    ["q5", "q7", "q8", "q11"].iter().any(|windowed| {
+        query == *windowed || query.starts_with(&format!("{}_", windowed))
+    })
+}
+
+/// Whether all state of `query` is checkpointed: it keeps its state in the operators of
+/// `nexmark::windows` or in the managed Q3 join, which write snapshots, or keeps none. The
+/// auction close of Q4 and Q6 writes snapshots too, but their averages don't.
+fn is_checkpointed(query: &str) -> bool {
+    match query {
+        "q0" | "q1" | "q2" | "q14" | "q21" | "q22" => true,
+        "q3_faster" | "q3_rocksdb" | "q3_faster_in_mem" | "q3_mem" | "q3_mem_native" => true,
+        _ => is_windowed(query),
+    }
+}
+
+/// Counts the events a windowed query dropped as late.
+fn count_late<S: Scope>(late: &Stream<S, Date>, counter: &Arc<AtomicUsize>) -> Stream<S, Date> {
+    let counter = counter.clone();
+    late.inspect(move |_| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    })
+}
+
 fn main() {
     let matches = App::new("word_count")
         .arg(
@@ -126,6 +161,32 @@ fn main() {
             Arg::with_name("print-rss")
                 .long("print-rss")
         )
+        .arg(
+            Arg::with_name("state-sizes")
+                .long("state-sizes")
+        )
+        .arg(
+            Arg::with_name("partitioning")
+                .long("partitioning")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("hot-keys")
+                .long("hot-keys")
+                .takes_value(true)
+                .required(false)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("exchange-records")
+                .long("exchange-records")
+        )
+        .arg(
+            Arg::with_name("pre-aggregate")
+                .long("pre-aggregate")
+        )
         .arg(
             Arg::with_name("latency-output")
                 .long("latency-output")
@@ -138,6 +199,90 @@ fn main() {
                 .takes_value(true)
                 .required(false)
         )
+        .arg(
+            Arg::with_name("approximate-distinct")
+                .long("approximate-distinct")
+        )
+        .arg(
+            Arg::with_name("side-input-size")
+                .long("side-input-size")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("side-input-file")
+                .long("side-input-file")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("snapshot-interval")
+                .long("snapshot-interval")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("join-ttl")
+                .long("join-ttl")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("allowed-lateness")
+                .long("allowed-lateness")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("checkpoint-dir")
+                .long("checkpoint-dir")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("checkpoint-interval")
+                .long("checkpoint-interval")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("recover-from")
+                .long("recover-from")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("inject-failure")
+                .long("inject-failure")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("failure-process")
+                .long("failure-process")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("started-at")
+                .long("started-at")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("failed-at")
+                .long("failed-at")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("migrate")
+                .long("migrate")
+                .takes_value(true)
+                .required(false)
+                .multiple(true)
+                .value_delimiter(" "),
+        )
         .arg(Arg::with_name("timely").multiple(true))
         .get_matches();
     let timely_args = matches
@@ -169,12 +314,148 @@ fn main() {
     let enable_rss = matches
         .occurrences_of("print-rss") > 0;
 
+    let enable_state_sizes = matches
+        .occurrences_of("state-sizes") > 0;
+
+    let enable_exchange_records = matches
+        .occurrences_of("exchange-records") > 0;
+
+    // Keyed exchanges assign keys to workers with this strategy.
+    let partitioning = matches
+        .value_of("partitioning")
+        .map_or(Ok(Partitioning::default()), Partitioning::parse)
+        .expect("couldn't parse partitioning");
+    // The exchanges of each operator given spread its hot keys over the workers.
+    let mut hot_keys = HotKeys::default();
+    for spec in matches.values_of("hot-keys").into_iter().flatten() {
+        hot_keys.add(spec).expect("couldn't parse hot keys");
+    }
+
+    // Q5 counts bids per auction on each worker before exchanging the counts, if set.
+    let pre_aggregate = matches
+        .occurrences_of("pre-aggregate") > 0;
+
+    let approximate_distinct = matches
+        .occurrences_of("approximate-distinct") > 0;
+
+    // Q19 emits top bids on every change unless a snapshot interval (in seconds) is given.
+    let top_n_emission = match matches.value_of("snapshot-interval") {
+        Some(interval) => TopNEmission::Snapshots(
+            interval
+                .parse::<usize>()
+                .expect("couldn't parse snapshot interval")
+                * 1_000_000_000,
+        ),
+        None => TopNEmission::Updates,
+    };
+
+    // Q3 evicts people and auctions this long (in seconds) after their event time, if given.
+    let join_ttl_ns = matches.value_of("join-ttl").map(|ttl| {
+        ttl.parse::<usize>().expect("couldn't parse join TTL") * 1_000_000_000
+    });
+
+    // Windowed queries keep windows open for this long (in seconds) after they end.
+    let allowed_lateness_ns = matches
+        .value_of("allowed-lateness")
+        .unwrap_or("0")
+        .parse::<usize>()
+        .expect("couldn't parse allowed lateness")
+        * 1_000_000_000;
+
+    // Window state is checkpointed into this directory every interval (in seconds), if given.
+    let checkpoint_dir = matches.value_of("checkpoint-dir").map(PathBuf::from);
+    let checkpoint_interval_ns = matches.value_of("checkpoint-interval").map(|interval| {
+        interval
+            .parse::<u64>()
+            .expect("couldn't parse checkpoint interval")
+            * 1_000_000_000
+    });
+    assert!(
+        checkpoint_interval_ns.is_none() || checkpoint_dir.is_some(),
+        "--checkpoint-interval requires --checkpoint-dir"
+    );
+
+    // Recover from the checkpoint in this directory, `<checkpoint-dir>/<epoch>`, if given.
+    let recover_from = matches.value_of("recover-from").map(PathBuf::from);
+
+    // Refuse to recover queries whose state isn't checkpointed, rather than resume them empty.
+    if recover_from.is_some() || matches.value_of("inject-failure").is_some() {
+        let unrecoverable: Vec<_> = queries.iter().filter(|query| !is_checkpointed(query)).collect();
+        assert!(
+            unrecoverable.is_empty(),
+            "can't recover {:?}, which keep state that isn't checkpointed",
+            unrecoverable
+        );
+    }
+
+    // Run as local processes and kill one of them after this long (in seconds), if given.
+    if let Some(fail_after) = matches.value_of("inject-failure") {
+        let fail_after = Duration::from_secs(
+            fail_after
+                .parse::<u64>()
+                .expect("couldn't parse failure time"),
+        );
+        let failed_process = matches
+            .value_of("failure-process")
+            .unwrap_or("0")
+            .parse::<usize>()
+            .expect("couldn't parse failure process");
+        assert!(
+            checkpoint_interval_ns.is_some(),
+            "--inject-failure requires --checkpoint-interval"
+        );
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        nexmark::tools::supervise_failure(
+            &args,
+            &timely_args,
+            fail_after,
+            failed_process,
+            checkpoint_dir.as_ref().unwrap(),
+        );
+        return;
+    }
+
+    // Processes started by a failure injection share the start of the run, and restarted ones
+    // know when the failure happened, both as Unix times in nanoseconds.
+    let started_at = matches
+        .value_of("started-at")
+        .map(|time| time.parse::<u64>().expect("couldn't parse start time"));
+    let failed_at = matches
+        .value_of("failed-at")
+        .map(|time| time.parse::<u64>().expect("couldn't parse failure time"));
+
+    // Managed Q3 and auction close move every other key bin to the next worker at each of these
+    // times (in seconds), if given.
+    let mut migrations_ns: Vec<u64> = matches.values_of("migrate").map_or(Vec::new(), |times| {
+        times
+            .map(|time| time.parse::<u64>().expect("couldn't parse migration time") * 1_000_000_000)
+            .collect()
+    });
+    migrations_ns.sort();
+    migrations_ns.dedup();
+
     let latency_output = matches
         .value_of("latency-output");
 
     let timeline_output = matches
         .value_of("timeline-output");
 
+    // Q13 joins bids against a bounded side input, loaded once per process.
+    let side_input = if queries.iter().any(|x| x.starts_with("q13")) {
+        match matches.value_of("side-input-file") {
+            Some(path) => SideInput::from_file(path).expect("couldn't read side input file"),
+            None => SideInput::generate(
+                matches
+                    .value_of("side-input-size")
+                    .unwrap_or("10000")
+                    .parse::<usize>()
+                    .expect("couldn't parse side input size"),
+            ),
+        }
+    } else {
+        SideInput::default()
+    };
+
     if enable_metrics {
         // Collect metrics
         env_logger::init();
@@ -192,20 +473,70 @@ fn main() {
         _ => None
     };
 
+    // Sample the size of each operator's state
+    let state_sizes = if enable_state_sizes {
+        StateSizes::new()
+    } else {
+        StateSizes::disabled()
+    };
+    let state_sizes_reporter = if enable_state_sizes {
+        Some(state_sizes.reporter())
+    } else {
+        None
+    };
+
+    // Count the records each keyed exchange sends to each worker
+    let record_counts = if enable_exchange_records {
+        RecordCounts::new()
+    } else {
+        RecordCounts::disabled()
+    };
+    let record_counts_report = record_counts.clone();
+
+    // Counts events that arrive too late for their windows, across the workers of this process
+    let report_late_events =
+        allowed_lateness_ns > 0 || queries.iter().any(|query| is_windowed(query));
+    let late_events = Arc::new(AtomicUsize::new(0));
+    let late_events_counter = late_events.clone();
+
     // define a new computational scope, in which to run NEXMark queries
-    let timelines: Vec<_> = timely::execute_from_args(
+    let (timelines, metrics): (Vec<_>, Vec<(_, Vec<MigrationMetrics>)>) = timely::execute_from_args(
         timely_args.into_iter(),
         move |worker, node_state_handle| {
             let peers = worker.peers();
             let index = worker.index();
+            let late_events = &late_events_counter;
+            let state_sizes = &state_sizes;
+            let partitioner = Partitioner::new(partitioning.clone(), hot_keys.clone(), peers, record_counts.clone());
+
+            let checkpoints = Checkpoints::new(index, checkpoint_dir.clone(), recover_from.clone());
+            let manifest = checkpoints.manifest();
 
             // Declare re-used input, control and probe handles.
             let mut input = InputHandle::new();
-            //let mut control_input = InputHandle::new();
+            let mut control_input = InputHandle::new();
             let mut probe = ProbeHandle::new();
 
+            // A recovering worker resumes its timeline at the epoch of the checkpoint, unless it
+            // continues the timeline of a run that started earlier.
+            let run_start = || {
+                let offset_ns = match (started_at, &manifest) {
+                    (Some(started_at), _) => nexmark::tools::unix_time_ns() - started_at,
+                    (None, Some(manifest)) => manifest.epoch as u64,
+                    (None, None) => 0,
+                };
+                ::std::time::Instant::now()
+                    .checked_sub(Duration::from_nanos(offset_ns))
+                    .expect("run started before the clock")
+            };
+
+            // Processing-time windows measure time from the start of the dataflow construction.
+            let processing_start = run_start();
+
             {
-                //let control = std::rc::Rc::new(timely::dataflow::operators::capture::event::link::EventLink::new());
+                let control = std::rc::Rc::new(
+                    timely::dataflow::operators::capture::event::link::EventLink::new(),
+                );
 
                 let bids = std::rc::Rc::new(
                     timely::dataflow::operators::capture::event::link::EventLink::new(),
@@ -225,16 +556,20 @@ fn main() {
                 );
 
                 let nexmark_input = NexmarkInput {
-                    //control: &control,
                     bids: &bids,
                     auctions: &auctions,
                     people: &people,
                     closed_auctions: &closed_auctions,
                     closed_auctions_flex: &closed_auctions_flex,
+                    control: &control,
+                    checkpoints: &checkpoints,
+                    state_sizes,
+                    partitioner: &partitioner,
                 };
 
                 let nexmark_timer = NexmarkTimer {
                     time_dilation: TIME_DILATION,
+                    start: processing_start,
                 };
 
                 worker.dataflow(
@@ -278,6 +613,7 @@ fn main() {
                         bids_stream.capture_into(bids.clone());
                         auctions_stream.capture_into(auctions.clone());
                         people_stream.capture_into(people.clone());
+                        control_input.to_stream(scope).capture_into(control.clone());
                     },
                 );
 
@@ -307,7 +643,7 @@ fn main() {
                 // Q3: Join some auctions. Native.
                 if queries.iter().any(|x| *x == "q3") {
                     worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
-                        ::nexmark::queries::q3(&nexmark_input, nexmark_timer, scope)
+                        ::nexmark::queries::q3(&nexmark_input, nexmark_timer, scope, join_ttl_ns)
                             .probe_with(&mut probe);
                     });
                 }
@@ -315,7 +651,7 @@ fn main() {
                 // Q3: Join some auctions. FASTER.
                 if queries.iter().any(|x| *x == "q3_faster") {
                     worker.dataflow::<_, _, _, FASTERBackend>(|scope, _| {
-                        ::nexmark::queries::q3_managed(&nexmark_input, nexmark_timer, scope)
+                        ::nexmark::queries::q3_managed(&nexmark_input, nexmark_timer, scope, join_ttl_ns)
                             .probe_with(&mut probe);
                     });
                 }
@@ -323,7 +659,7 @@ fn main() {
                 // Q3: Join some auctions. RocksDB.
                 if queries.iter().any(|x| *x == "q3_rocksdb") {
                     worker.dataflow::<_, _, _, RocksDBBackend>(|scope, _| {
-                        ::nexmark::queries::q3_managed(&nexmark_input, nexmark_timer, scope)
+                        ::nexmark::queries::q3_managed(&nexmark_input, nexmark_timer, scope, join_ttl_ns)
                             .probe_with(&mut probe);
                     });
                 }
@@ -331,7 +667,7 @@ fn main() {
                 // Q3: Join some auctions. FASTER One Instance Per Node.
                 if queries.iter().any(|x| *x == "q3_faster_node") {
                     worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
-                        ::nexmark::queries::q3_managed_node(&nexmark_input, nexmark_timer, scope, &node_state_handle)
+                        ::nexmark::queries::q3_managed_node(&nexmark_input, nexmark_timer, scope, &node_state_handle, join_ttl_ns)
                             .probe_with(&mut probe);
                     });
                 }
@@ -339,7 +675,7 @@ fn main() {
                 // Q3: Join some auctions. FASTER In Memory.
                 if queries.iter().any(|x| *x == "q3_faster_in_mem") {
                     worker.dataflow::<_, _, _, FASTERInMemoryBackend>(|scope, _| {
-                        ::nexmark::queries::q3_managed(&nexmark_input, nexmark_timer, scope)
+                        ::nexmark::queries::q3_managed(&nexmark_input, nexmark_timer, scope, join_ttl_ns)
                             .probe_with(&mut probe);
                     });
                 }
@@ -347,7 +683,7 @@ fn main() {
                 // Q3: Join some auctions. In Mem.
                 if queries.iter().any(|x| *x == "q3_mem") {
                     worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
-                        ::nexmark::queries::q3_managed(&nexmark_input, nexmark_timer, scope)
+                        ::nexmark::queries::q3_managed(&nexmark_input, nexmark_timer, scope, join_ttl_ns)
                             .probe_with(&mut probe);
                     });
                 }
@@ -355,7 +691,7 @@ fn main() {
                 // Q3: Join some auctions. In Mem Native.
                 if queries.iter().any(|x| *x == "q3_mem_native") {
                     worker.dataflow::<_, _, _, InMemoryNativeBackend>(|scope, _| {
-                        ::nexmark::queries::q3_managed(&nexmark_input, nexmark_timer, scope)
+                        ::nexmark::queries::q3_managed(&nexmark_input, nexmark_timer, scope, join_ttl_ns)
                             .probe_with(&mut probe);
                     });
                 }
@@ -437,14 +773,18 @@ fn main() {
                     let window_slice_count = 60;
                     let window_slide_ns = 1_000_000_000;
                     worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
-                        ::nexmark::queries::q5(
+                        let (output, late) = ::nexmark::queries::q5(
                             &nexmark_input,
                             nexmark_timer,
                             scope,
                             window_slice_count,
                             window_slide_ns,
-                        )
-                        .probe_with(&mut probe);
+                            allowed_lateness_ns,
+                            pre_aggregate,
+                            Backing::Native,
+                        );
+                        output.probe_with(&mut probe);
+                        count_late(&late, late_events).probe_with(&mut probe);
                     });
                 }
 
@@ -455,14 +795,18 @@ fn main() {
                     let window_slice_count = 60;
                     let window_slide_ns = 1_000_000_000;
                     worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
-                        ::nexmark::queries::q5_managed(
+                        let (output, late) = ::nexmark::queries::q5(
                             &nexmark_input,
                             nexmark_timer,
                             scope,
                             window_slice_count,
                             window_slide_ns,
-                        )
-                        .probe_with(&mut probe);
+                            allowed_lateness_ns,
+                            pre_aggregate,
+                            Backing::Managed,
+                        );
+                        output.probe_with(&mut probe);
+                        count_late(&late, late_events).probe_with(&mut probe);
                     });
                 }
 
@@ -473,14 +817,18 @@ fn main() {
                     let window_slice_count = 60;
                     let window_slide_ns = 1_000_000_000;
                     worker.dataflow::<_, _, _, FASTERBackend>(|scope, _| {
-                        ::nexmark::queries::q5_managed(
+                        let (output, late) = ::nexmark::queries::q5(
                             &nexmark_input,
                             nexmark_timer,
                             scope,
                             window_slice_count,
                             window_slide_ns,
-                        )
-                        .probe_with(&mut probe);
+                            allowed_lateness_ns,
+                            pre_aggregate,
+                            Backing::Managed,
+                        );
+                        output.probe_with(&mut probe);
+                        count_late(&late, late_events).probe_with(&mut probe);
                     });
                 }
 
@@ -491,14 +839,18 @@ fn main() {
                     let window_slice_count = 60;
                     let window_slide_ns = 1_000_000_000;
                     worker.dataflow::<_, _, _, RocksDBBackend>(|scope, _| {
-                        ::nexmark::queries::q5_managed(
+                        let (output, late) = ::nexmark::queries::q5(
                             &nexmark_input,
                             nexmark_timer,
                             scope,
                             window_slice_count,
                             window_slide_ns,
-                        )
-                            .probe_with(&mut probe);
+                            allowed_lateness_ns,
+                            pre_aggregate,
+                            Backing::Managed,
+                        );
+                        output.probe_with(&mut probe);
+                        count_late(&late, late_events).probe_with(&mut probe);
                     });
                 }
 
@@ -509,14 +861,18 @@ fn main() {
                     let window_slice_count = 60;
                     let window_slide_ns = 1_000_000_000;
                     worker.dataflow::<_, _, _, FASTERInMemoryBackend>(|scope, _| {
-                        ::nexmark::queries::q5_managed(
+                        let (output, late) = ::nexmark::queries::q5(
                             &nexmark_input,
                             nexmark_timer,
                             scope,
                             window_slice_count,
                             window_slide_ns,
-                        )
-                        .probe_with(&mut probe);
+                            allowed_lateness_ns,
+                            pre_aggregate,
+                            Backing::Managed,
+                        );
+                        output.probe_with(&mut probe);
+                        count_late(&late, late_events).probe_with(&mut probe);
                     });
                 }
 
@@ -596,13 +952,16 @@ fn main() {
                         // Window ticks every 10 seconds.
                         // NEXMark default is different: ticks every 60s
                         let window_size_ns = 10_000_000_000;
-                        ::nexmark::queries::q7(
+                        let (output, late) = ::nexmark::queries::q7(
                             &nexmark_input,
                             nexmark_timer,
                             scope,
                             window_size_ns,
-                        )
-                        .probe_with(&mut probe);
+                            allowed_lateness_ns,
+                            Backing::Native,
+                        );
+                        output.probe_with(&mut probe);
+                        count_late(&late, late_events).probe_with(&mut probe);
                     });
                 }
 
@@ -612,13 +971,16 @@ fn main() {
                         // Window ticks every 10 seconds.
                         // NEXMark default is different: ticks every 60s
                         let window_size_ns = 10_000_000_000;
-                        ::nexmark::queries::q7_managed(
+                        let (output, late) = ::nexmark::queries::q7(
                             &nexmark_input,
                             nexmark_timer,
                             scope,
                             window_size_ns,
-                        )
-                        .probe_with(&mut probe);
+                            allowed_lateness_ns,
+                            Backing::Managed,
+                        );
+                        output.probe_with(&mut probe);
+                        count_late(&late, late_events).probe_with(&mut probe);
                     });
                 }
 
@@ -628,13 +990,16 @@ fn main() {
                         // Window ticks every 10 seconds.
                         // NEXMark default is different: ticks every 60s
                         let window_size_ns = 10_000_000_000;
-                        ::nexmark::queries::q7_managed(
+                        let (output, late) = ::nexmark::queries::q7(
                             &nexmark_input,
                             nexmark_timer,
                             scope,
                             window_size_ns,
-                        )
-                        .probe_with(&mut probe);
+                            allowed_lateness_ns,
+                            Backing::Managed,
+                        );
+                        output.probe_with(&mut probe);
+                        count_late(&late, late_events).probe_with(&mut probe);
                     });
                 }
 
@@ -644,13 +1009,16 @@ fn main() {
                         // Window ticks every 10 seconds.
                         // NEXMark default is different: ticks every 60s
                         let window_size_ns = 10_000_000_000;
-                        ::nexmark::queries::q7_managed(
+                        let (output, late) = ::nexmark::queries::q7(
                             &nexmark_input,
                             nexmark_timer,
                             scope,
                             window_size_ns,
-                        )
-                            .probe_with(&mut probe);
+                            allowed_lateness_ns,
+                            Backing::Managed,
+                        );
+                        output.probe_with(&mut probe);
+                        count_late(&late, late_events).probe_with(&mut probe);
                     });
                 }
 
@@ -660,13 +1028,16 @@ fn main() {
                         // Window ticks every 10 seconds.
                         // NEXMark default is different: ticks every 60s
                         let window_size_ns = 10_000_000_000;
-                        ::nexmark::queries::q7_managed(
+                        let (output, late) = ::nexmark::queries::q7(
                             &nexmark_input,
                             nexmark_timer,
                             scope,
                             window_size_ns,
-                        )
-                        .probe_with(&mut probe);
+                            allowed_lateness_ns,
+                            Backing::Managed,
+                        );
+                        output.probe_with(&mut probe);
+                        count_late(&late, late_events).probe_with(&mut probe);
                     });
                 }
 
@@ -674,13 +1045,16 @@ fn main() {
                 if queries.iter().any(|x| *x == "q8") {
                     worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
                         let window_size_ns = 12 * 60 * 60 * 1_000_000_000;
-                        ::nexmark::queries::q8(
+                        let (output, late) = ::nexmark::queries::q8(
                             &nexmark_input,
                             nexmark_timer,
                             scope,
                             window_size_ns,
-                        )
-                        .probe_with(&mut probe);
+                            allowed_lateness_ns,
+                            Backing::Native,
+                        );
+                        output.probe_with(&mut probe);
+                        count_late(&late, late_events).probe_with(&mut probe);
                     });
                 }
 
@@ -688,13 +1062,16 @@ fn main() {
                 if queries.iter().any(|x| *x == "q8_mem") {
                     worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
                         let window_size_ns = 12 * 60 * 60 * 1_000_000_000;
-                        ::nexmark::queries::q8_managed(
+                        let (output, late) = ::nexmark::queries::q8(
                             &nexmark_input,
                             nexmark_timer,
                             scope,
                             window_size_ns,
-                        )
-                        .probe_with(&mut probe);
+                            allowed_lateness_ns,
+                            Backing::Managed,
+                        );
+                        output.probe_with(&mut probe);
+                        count_late(&late, late_events).probe_with(&mut probe);
                     });
                 }
 
@@ -702,13 +1079,16 @@ fn main() {
                 if queries.iter().any(|x| *x == "q8_faster") {
                     worker.dataflow::<_, _, _, FASTERBackend>(|scope, _| {
                         let window_size_ns = 12 * 60 * 60 * 1_000_000_000;
-                        ::nexmark::queries::q8_managed(
+                        let (output, late) = ::nexmark::queries::q8(
                             &nexmark_input,
                             nexmark_timer,
                             scope,
                             window_size_ns,
-                        )
-                        .probe_with(&mut probe);
+                            allowed_lateness_ns,
+                            Backing::Managed,
+                        );
+                        output.probe_with(&mut probe);
+                        count_late(&late, late_events).probe_with(&mut probe);
                     });
                 }
 
@@ -716,13 +1096,16 @@ fn main() {
                 if queries.iter().any(|x| *x == "q8_rocksdb") {
                     worker.dataflow::<_, _, _, RocksDBBackend>(|scope, _| {
                         let window_size_ns = 12 * 60 * 60 * 1_000_000_000;
-                        ::nexmark::queries::q8_managed(
+                        let (output, late) = ::nexmark::queries::q8(
                             &nexmark_input,
                             nexmark_timer,
                             scope,
                             window_size_ns,
-                        )
-                            .probe_with(&mut probe);
+                            allowed_lateness_ns,
+                            Backing::Managed,
+                        );
+                        output.probe_with(&mut probe);
+                        count_late(&late, late_events).probe_with(&mut probe);
                     });
                 }
 
@@ -730,73 +1113,709 @@ fn main() {
                 if queries.iter().any(|x| *x == "q8_faster_in_mem") {
                     worker.dataflow::<_, _, _, FASTERInMemoryBackend>(|scope, _| {
                         let window_size_ns = 12 * 60 * 60 * 1_000_000_000;
-                        ::nexmark::queries::q8_managed(
+                        let (output, late) = ::nexmark::queries::q8(
                             &nexmark_input,
                             nexmark_timer,
                             scope,
                             window_size_ns,
+                            allowed_lateness_ns,
+                            Backing::Managed,
+                        );
+                        output.probe_with(&mut probe);
+                        count_late(&late, late_events).probe_with(&mut probe);
+                    });
+                }
+
+                // Q11. Bids per bidder session. Native.
+                if queries.iter().any(|x| *x == "q11") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        // Sessions close after 10 seconds without a bid.
+                        let session_gap_ns = 10_000_000_000;
+                        ::nexmark::queries::q11(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            session_gap_ns,
+                            Backing::Native,
                         )
                         .probe_with(&mut probe);
                     });
                 }
-            }
-
-            let mut config1 = nexmark::config::Config::new();
-            // 0.06*60*60*12 = 0.06*60*60*12
-            // auction_proportion*sec_in_12h
-            config1.insert("in-flight-auctions", format!("{}", rate * 2592));
-            config1.insert("events-per-second", format!("{}", rate));
-            config1.insert("first-event-number", format!("{}", index));
-            let mut config = nexmark::config::NEXMarkConfig::new(&config1);
 
-            let count = 1;
-            input.advance_to(count);
-            while probe.less_than(&count) {
-                worker.step();
-            }
-
-            let timer = ::std::time::Instant::now();
+                // Q11. Bids per bidder session. In Mem.
+                if queries.iter().any(|x| *x == "q11_mem") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        // Sessions close after 10 seconds without a bid.
+                        let session_gap_ns = 10_000_000_000;
+                        ::nexmark::queries::q11(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            session_gap_ns,
+                            Backing::Managed,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
 
-            // Establish a start of the computation.
-            let elapsed_ns = timer.elapsed().to_nanos();
-            config.base_time_ns = elapsed_ns as usize;
+                // Q11. Bids per bidder session. FASTER.
+                if queries.iter().any(|x| *x == "q11_faster") {
+                    worker.dataflow::<_, _, _, FASTERBackend>(|scope, _| {
+                        // Sessions close after 10 seconds without a bid.
+                        let session_gap_ns = 10_000_000_000;
+                        ::nexmark::queries::q11(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            session_gap_ns,
+                            Backing::Managed,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
 
-            use rand::rngs::SmallRng;
-            use rand::SeedableRng;
-            assert!(worker.peers() < 256);
-            let mut rng = SmallRng::from_seed([worker.peers() as u8; 16]);
+                // Q11. Bids per bidder session. RocksDB.
+                if queries.iter().any(|x| *x == "q11_rocksdb") {
+                    worker.dataflow::<_, _, _, RocksDBBackend>(|scope, _| {
+                        // Sessions close after 10 seconds without a bid.
+                        let session_gap_ns = 10_000_000_000;
+                        ::nexmark::queries::q11(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            session_gap_ns,
+                            Backing::Managed,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
 
-            let input_times = {
-                let config = config.clone();
-                move || {
-                    nexmark::config::NexMarkInputTimes::new(
-                        config.clone(),
-                        duration_ns,
-                        TIME_DILATION,
-                        peers,
-                    )
+                // Q11. Bids per bidder session. FASTER In Mem.
+                if queries.iter().any(|x| *x == "q11_faster_in_mem") {
+                    worker.dataflow::<_, _, _, FASTERInMemoryBackend>(|scope, _| {
+                        // Sessions close after 10 seconds without a bid.
+                        let session_gap_ns = 10_000_000_000;
+                        ::nexmark::queries::q11(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            session_gap_ns,
+                            Backing::Managed,
+                        )
+                        .probe_with(&mut probe);
+                    });
                 }
-            };
 
-            let mut output_metric_collector =
-                ::streaming_harness::output::default::hdrhist_timeline_collector(
-                    input_times(),
-                    0,
-                    2_000_000_000,
-                    duration_ns - 2_000_000_000,
-                    duration_ns,
-                    250_000_000,
-                );
+                // Q12. Bids per bidder in processing time windows. Native.
+                if queries.iter().any(|x| *x == "q12") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        // Windows tick every 10 seconds of wall-clock time.
+                        let window_size_ns = 10_000_000_000;
+                        ::nexmark::queries::q12(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            window_size_ns,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
 
-            let mut events_so_far = 0;
+                // Q12. Bids per bidder in processing time windows. In Mem.
+                if queries.iter().any(|x| *x == "q12_mem") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        // Windows tick every 10 seconds of wall-clock time.
+                        let window_size_ns = 10_000_000_000;
+                        ::nexmark::queries::q12_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            window_size_ns,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
 
-            let mut input_times_gen =
-                ::streaming_harness::input::SyntheticInputTimeGenerator::new(input_times());
+                // Q12. Bids per bidder in processing time windows. FASTER.
+                if queries.iter().any(|x| *x == "q12_faster") {
+                    worker.dataflow::<_, _, _, FASTERBackend>(|scope, _| {
+                        // Windows tick every 10 seconds of wall-clock time.
+                        let window_size_ns = 10_000_000_000;
+                        ::nexmark::queries::q12_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            window_size_ns,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
 
-            let mut input = Some(input);
+                // Q12. Bids per bidder in processing time windows. RocksDB.
+                if queries.iter().any(|x| *x == "q12_rocksdb") {
+                    worker.dataflow::<_, _, _, RocksDBBackend>(|scope, _| {
+                        // Windows tick every 10 seconds of wall-clock time.
+                        let window_size_ns = 10_000_000_000;
+                        ::nexmark::queries::q12_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            window_size_ns,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q12. Bids per bidder in processing time windows. FASTER In Mem.
+                if queries.iter().any(|x| *x == "q12_faster_in_mem") {
+                    worker.dataflow::<_, _, _, FASTERInMemoryBackend>(|scope, _| {
+                        // Windows tick every 10 seconds of wall-clock time.
+                        let window_size_ns = 10_000_000_000;
+                        ::nexmark::queries::q12_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            window_size_ns,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q13. Join bids with a bounded side input. Native.
+                if queries.iter().any(|x| *x == "q13") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        ::nexmark::queries::q13(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            &side_input,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q13. Join bids with a bounded side input. In Mem.
+                if queries.iter().any(|x| *x == "q13_mem") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        ::nexmark::queries::q13_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            &side_input,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q13. Join bids with a bounded side input. FASTER.
+                if queries.iter().any(|x| *x == "q13_faster") {
+                    worker.dataflow::<_, _, _, FASTERBackend>(|scope, _| {
+                        ::nexmark::queries::q13_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            &side_input,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q13. Join bids with a bounded side input. RocksDB.
+                if queries.iter().any(|x| *x == "q13_rocksdb") {
+                    worker.dataflow::<_, _, _, RocksDBBackend>(|scope, _| {
+                        ::nexmark::queries::q13_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            &side_input,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q13. Join bids with a bounded side input. FASTER In Mem.
+                if queries.iter().any(|x| *x == "q13_faster_in_mem") {
+                    worker.dataflow::<_, _, _, FASTERInMemoryBackend>(|scope, _| {
+                        ::nexmark::queries::q13_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            &side_input,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q14: Filter, convert and classify bids. Native.
+                if queries.iter().any(|x| *x == "q14") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        // Bids made between 06:00 and 22:00 of their event time.
+                        let hours = 6..22;
+                        ::nexmark::queries::q14(&nexmark_input, nexmark_timer, scope, hours)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q15. Daily bid statistics. Native.
+                if queries.iter().any(|x| *x == "q15") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        let day_ns = 24 * 60 * 60 * 1_000_000_000;
+                        ::nexmark::queries::q15(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            day_ns,
+                            approximate_distinct,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q15. Daily bid statistics. In Mem.
+                if queries.iter().any(|x| *x == "q15_mem") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        let day_ns = 24 * 60 * 60 * 1_000_000_000;
+                        ::nexmark::queries::q15_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            day_ns,
+                            approximate_distinct,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q15. Daily bid statistics. FASTER.
+                if queries.iter().any(|x| *x == "q15_faster") {
+                    worker.dataflow::<_, _, _, FASTERBackend>(|scope, _| {
+                        let day_ns = 24 * 60 * 60 * 1_000_000_000;
+                        ::nexmark::queries::q15_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            day_ns,
+                            approximate_distinct,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q15. Daily bid statistics. RocksDB.
+                if queries.iter().any(|x| *x == "q15_rocksdb") {
+                    worker.dataflow::<_, _, _, RocksDBBackend>(|scope, _| {
+                        let day_ns = 24 * 60 * 60 * 1_000_000_000;
+                        ::nexmark::queries::q15_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            day_ns,
+                            approximate_distinct,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q15. Daily bid statistics. FASTER In Mem.
+                if queries.iter().any(|x| *x == "q15_faster_in_mem") {
+                    worker.dataflow::<_, _, _, FASTERInMemoryBackend>(|scope, _| {
+                        let day_ns = 24 * 60 * 60 * 1_000_000_000;
+                        ::nexmark::queries::q15_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            day_ns,
+                            approximate_distinct,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q16. Daily bid statistics per channel. Native.
+                if queries.iter().any(|x| *x == "q16") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        let day_ns = 24 * 60 * 60 * 1_000_000_000;
+                        ::nexmark::queries::q16(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            day_ns,
+                            approximate_distinct,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q16. Daily bid statistics per channel. In Mem.
+                if queries.iter().any(|x| *x == "q16_mem") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        let day_ns = 24 * 60 * 60 * 1_000_000_000;
+                        ::nexmark::queries::q16_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            day_ns,
+                            approximate_distinct,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q16. Daily bid statistics per channel. FASTER.
+                if queries.iter().any(|x| *x == "q16_faster") {
+                    worker.dataflow::<_, _, _, FASTERBackend>(|scope, _| {
+                        let day_ns = 24 * 60 * 60 * 1_000_000_000;
+                        ::nexmark::queries::q16_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            day_ns,
+                            approximate_distinct,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q16. Daily bid statistics per channel. RocksDB.
+                if queries.iter().any(|x| *x == "q16_rocksdb") {
+                    worker.dataflow::<_, _, _, RocksDBBackend>(|scope, _| {
+                        let day_ns = 24 * 60 * 60 * 1_000_000_000;
+                        ::nexmark::queries::q16_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            day_ns,
+                            approximate_distinct,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q16. Daily bid statistics per channel. FASTER In Mem.
+                if queries.iter().any(|x| *x == "q16_faster_in_mem") {
+                    worker.dataflow::<_, _, _, FASTERInMemoryBackend>(|scope, _| {
+                        let day_ns = 24 * 60 * 60 * 1_000_000_000;
+                        ::nexmark::queries::q16_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            day_ns,
+                            approximate_distinct,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q21: Add channel ids to bids. Native.
+                if queries.iter().any(|x| *x == "q21") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        ::nexmark::queries::q21(&nexmark_input, nexmark_timer, scope)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q22: Split bid urls into directories. Native.
+                if queries.iter().any(|x| *x == "q22") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        ::nexmark::queries::q22(&nexmark_input, nexmark_timer, scope)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q17. Daily bid statistics per auction. Native.
+                if queries.iter().any(|x| *x == "q17") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        let day_ns = 24 * 60 * 60 * 1_000_000_000;
+                        ::nexmark::queries::q17(&nexmark_input, nexmark_timer, scope, day_ns)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q17. Daily bid statistics per auction. In Mem.
+                if queries.iter().any(|x| *x == "q17_mem") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        let day_ns = 24 * 60 * 60 * 1_000_000_000;
+                        ::nexmark::queries::q17_managed(&nexmark_input, nexmark_timer, scope, day_ns)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q17. Daily bid statistics per auction. FASTER.
+                if queries.iter().any(|x| *x == "q17_faster") {
+                    worker.dataflow::<_, _, _, FASTERBackend>(|scope, _| {
+                        let day_ns = 24 * 60 * 60 * 1_000_000_000;
+                        ::nexmark::queries::q17_managed(&nexmark_input, nexmark_timer, scope, day_ns)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q17. Daily bid statistics per auction. RocksDB.
+                if queries.iter().any(|x| *x == "q17_rocksdb") {
+                    worker.dataflow::<_, _, _, RocksDBBackend>(|scope, _| {
+                        let day_ns = 24 * 60 * 60 * 1_000_000_000;
+                        ::nexmark::queries::q17_managed(&nexmark_input, nexmark_timer, scope, day_ns)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q17. Daily bid statistics per auction. FASTER In Mem.
+                if queries.iter().any(|x| *x == "q17_faster_in_mem") {
+                    worker.dataflow::<_, _, _, FASTERInMemoryBackend>(|scope, _| {
+                        let day_ns = 24 * 60 * 60 * 1_000_000_000;
+                        ::nexmark::queries::q17_managed(&nexmark_input, nexmark_timer, scope, day_ns)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q18. Latest bid per bidder and auction. Native.
+                if queries.iter().any(|x| *x == "q18") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        ::nexmark::queries::q18(&nexmark_input, nexmark_timer, scope)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q18. Latest bid per bidder and auction. In Mem.
+                if queries.iter().any(|x| *x == "q18_mem") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        ::nexmark::queries::q18_managed(&nexmark_input, nexmark_timer, scope)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q18. Latest bid per bidder and auction. FASTER.
+                if queries.iter().any(|x| *x == "q18_faster") {
+                    worker.dataflow::<_, _, _, FASTERBackend>(|scope, _| {
+                        ::nexmark::queries::q18_managed(&nexmark_input, nexmark_timer, scope)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q18. Latest bid per bidder and auction. RocksDB.
+                if queries.iter().any(|x| *x == "q18_rocksdb") {
+                    worker.dataflow::<_, _, _, RocksDBBackend>(|scope, _| {
+                        ::nexmark::queries::q18_managed(&nexmark_input, nexmark_timer, scope)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q18. Latest bid per bidder and auction. FASTER In Mem.
+                if queries.iter().any(|x| *x == "q18_faster_in_mem") {
+                    worker.dataflow::<_, _, _, FASTERInMemoryBackend>(|scope, _| {
+                        ::nexmark::queries::q18_managed(&nexmark_input, nexmark_timer, scope)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q19. Top bids per auction. Native.
+                if queries.iter().any(|x| *x == "q19") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        ::nexmark::queries::q19(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            top_n_emission,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q19. Top bids per auction. In Mem.
+                if queries.iter().any(|x| *x == "q19_mem") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        ::nexmark::queries::q19_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            top_n_emission,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q19. Top bids per auction. FASTER.
+                if queries.iter().any(|x| *x == "q19_faster") {
+                    worker.dataflow::<_, _, _, FASTERBackend>(|scope, _| {
+                        ::nexmark::queries::q19_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            top_n_emission,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q19. Top bids per auction. RocksDB.
+                if queries.iter().any(|x| *x == "q19_rocksdb") {
+                    worker.dataflow::<_, _, _, RocksDBBackend>(|scope, _| {
+                        ::nexmark::queries::q19_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            top_n_emission,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q19. Top bids per auction. FASTER In Mem.
+                if queries.iter().any(|x| *x == "q19_faster_in_mem") {
+                    worker.dataflow::<_, _, _, FASTERInMemoryBackend>(|scope, _| {
+                        ::nexmark::queries::q19_managed(
+                            &nexmark_input,
+                            nexmark_timer,
+                            scope,
+                            top_n_emission,
+                        )
+                        .probe_with(&mut probe);
+                    });
+                }
+
+                // Q20. Bids joined with their auction in a category. Native.
+                if queries.iter().any(|x| *x == "q20") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        ::nexmark::queries::q20(&nexmark_input, nexmark_timer, scope, 10)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q20. Bids joined with their auction in a category. In Mem.
+                if queries.iter().any(|x| *x == "q20_mem") {
+                    worker.dataflow::<_, _, _, InMemoryBackend>(|scope, _| {
+                        ::nexmark::queries::q20_managed(&nexmark_input, nexmark_timer, scope, 10)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q20. Bids joined with their auction in a category. FASTER.
+                if queries.iter().any(|x| *x == "q20_faster") {
+                    worker.dataflow::<_, _, _, FASTERBackend>(|scope, _| {
+                        ::nexmark::queries::q20_managed(&nexmark_input, nexmark_timer, scope, 10)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q20. Bids joined with their auction in a category. RocksDB.
+                if queries.iter().any(|x| *x == "q20_rocksdb") {
+                    worker.dataflow::<_, _, _, RocksDBBackend>(|scope, _| {
+                        ::nexmark::queries::q20_managed(&nexmark_input, nexmark_timer, scope, 10)
+                            .probe_with(&mut probe);
+                    });
+                }
+
+                // Q20. Bids joined with their auction in a category. FASTER In Mem.
+                if queries.iter().any(|x| *x == "q20_faster_in_mem") {
+                    worker.dataflow::<_, _, _, FASTERInMemoryBackend>(|scope, _| {
+                        ::nexmark::queries::q20_managed(&nexmark_input, nexmark_timer, scope, 10)
+                            .probe_with(&mut probe);
+                    });
+                }
+            }
+
+            let mut config1 = nexmark::config::Config::new();
+            // 0.06*60*60*12 = 0.06*60*60*12
+            // auction_proportion*sec_in_12h
+            config1.insert("in-flight-auctions", format!("{}", rate * 2592));
+            config1.insert("events-per-second", format!("{}", rate));
+            config1.insert("first-event-number", format!("{}", index));
+            let mut config = nexmark::config::NEXMarkConfig::new(&config1);
+
+            let count = 1;
+            input.advance_to(count);
+            control_input.advance_to(count);
+            while probe.less_than(&count) {
+                worker.step();
+            }
+
+            let timer = run_start();
+
+            // Establish a start of the computation, or continue that of the checkpoint.
+            let elapsed_ns = timer.elapsed().to_nanos();
+            config.base_time_ns = elapsed_ns as usize;
+            if let Some(ref manifest) = manifest {
+                config.base_time_ns = manifest.base_time_ns;
+                input.advance_to(manifest.epoch);
+                control_input.advance_to(manifest.epoch);
+            }
+            let resume_events = manifest.as_ref().map_or(0, |manifest| manifest.events_so_far);
+
+            use rand::rngs::SmallRng;
+            use rand::SeedableRng;
+            assert!(worker.peers() < 256);
+            let mut rng = SmallRng::from_seed([worker.peers() as u8; 16]);
+
+            let input_times = {
+                let config = config.clone();
+                move || {
+                    nexmark::config::NexMarkInputTimes::new(
+                        config.clone(),
+                        duration_ns,
+                        TIME_DILATION,
+                        peers,
+                    )
+                    .resume(resume_events)
+                }
+            };
+
+            let mut output_metric_collector =
+                ::streaming_harness::output::default::hdrhist_timeline_collector(
+                    input_times(),
+                    0,
+                    2_000_000_000,
+                    duration_ns - 2_000_000_000,
+                    duration_ns,
+                    250_000_000,
+                );
+
+            let mut events_so_far = 0;
+            // Regenerate the events sent before the checkpoint, so the generator resumes in the
+            // same state.
+            while events_so_far < resume_events {
+                Event::create(events_so_far, &mut rng, &mut config);
+                events_so_far += worker.peers();
+            }
+
+            let mut input_times_gen =
+                ::streaming_harness::input::SyntheticInputTimeGenerator::new(input_times());
+
+            let mut input = Some(input);
+            let mut control_input = Some(control_input);
 
             let mut last_ns = 0;
 
+            // Checkpoints are taken every interval of input time, at the same epochs on all workers.
+            let resume_ns = manifest.as_ref().map_or(0, |manifest| manifest.epoch - count) as u64;
+            let mut next_checkpoint_ns =
+                checkpoint_interval_ns.map(|interval_ns| resume_ns + interval_ns);
+
+            // A worker restarted after a failure measures how long it was down, and how long it
+            // takes to process the input replayed since the checkpoint.
+            let recovered_ns = timer.elapsed().to_nanos();
+            let mut recovery = failed_at.map(|failed_at| RecoveryMetrics {
+                downtime_ns: nexmark::tools::unix_time_ns().saturating_sub(failed_at),
+                ..Default::default()
+            });
+            let mut catching_up = recovery.is_some();
+            let mut replaying = recovery.is_some();
+
+            // Migrations are sent at the same epochs on all workers, which track the owner of
+            // each bin alike. A migration completes once its epoch is processed. The operators
+            // restore the routing of the migrations before a checkpoint, which are replayed here
+            // only to track the owners.
+            let (replayed_ns, migrations_ns): (Vec<u64>, Vec<u64>) = migrations_ns.iter().partition(|at_ns| **at_ns < resume_ns);
+            let mut migrations_ns = migrations_ns.into_iter().peekable();
+            let mut owners: Vec<usize> = (0..BINS).map(|bin| initial_owner(bin, peers)).collect();
+            if peers > 1 {
+                for _ in replayed_ns.iter() {
+                    for bin in (0..BINS).step_by(2) {
+                        owners[bin] = (owners[bin] + 1) % peers;
+                    }
+                }
+            }
+            let mut migrating: Vec<MigrationMetrics> = Vec::new();
+            let mut migrations: Vec<MigrationMetrics> = Vec::new();
+
             loop {
                 let elapsed_ns = timer.elapsed().to_nanos();
                 let wait_ns = last_ns;
@@ -806,10 +1825,92 @@ fn main() {
                 output_metric_collector
                     .acknowledge_while(elapsed_ns, |t| !probe.less_than(&(t as usize + count)));
 
+                // The first input replayed, sent at the epoch of the checkpoint, waited the longest.
+                if replaying && !probe.less_than(&(resume_ns as usize + count + 1)) {
+                    recovery.as_mut().unwrap().latency_spike_ns = elapsed_ns - resume_ns;
+                    replaying = false;
+                }
+
+                if catching_up && !probe.less_than(&(recovered_ns as usize + count)) {
+                    recovery.as_mut().unwrap().catch_up_ns = elapsed_ns - recovered_ns;
+                    catching_up = false;
+                }
+
+                let (completed, pending): (Vec<_>, Vec<_>) = migrating
+                    .into_iter()
+                    .partition(|migration| !probe.less_than(&(migration.at_ns as usize + count + 1)));
+                migrating = pending;
+                for mut migration in completed.into_iter() {
+                    migration.duration_ns = elapsed_ns.saturating_sub(migration.at_ns);
+                    migrations.push(migration);
+                }
+
                 if input.is_none() {
                     break;
                 }
 
+                // Send the input before each checkpoint and migration due by now, in time order, so
+                // that the input never goes back to an earlier epoch.
+                loop {
+                    let checkpoint_due = next_checkpoint_ns.filter(|next_ns| target_ns >= *next_ns);
+                    let migration_due = migrations_ns.peek().cloned().filter(|at_ns| target_ns >= *at_ns);
+                    match (checkpoint_due, migration_due) {
+                        // Send the input before the checkpoint, and no later input until it completes.
+                        (Some(checkpoint_ns), migration_ns) if migration_ns.map_or(true, |at_ns| checkpoint_ns <= at_ns) => {
+                            if let Some(it) = input_times_gen.iter_until(checkpoint_ns) {
+                                let input = input.as_mut().unwrap();
+                                for _t in it {
+                                    input.send(Event::create(events_so_far, &mut rng, &mut config));
+                                    events_so_far += worker.peers();
+                                }
+                                let epoch = checkpoint_ns as usize + count;
+                                input.advance_to(epoch);
+                                control_input.as_mut().unwrap().advance_to(epoch);
+                                checkpoints.start(epoch);
+                                while probe.less_than(&epoch) || !checkpoints.is_written() {
+                                    worker.step();
+                                }
+                                checkpoints.finish(events_so_far, config.base_time_ns);
+                            }
+                            next_checkpoint_ns = checkpoint_interval_ns.map(|interval_ns| checkpoint_ns + interval_ns);
+                        }
+                        // Send the input before the migration, and move every other bin to the next
+                        // worker from its epoch on.
+                        (_, Some(migration_ns)) => {
+                            migrations_ns.next();
+                            if let Some(it) = input_times_gen.iter_until(migration_ns) {
+                                let input = input.as_mut().unwrap();
+                                let control_input = control_input.as_mut().unwrap();
+                                for _t in it {
+                                    input.send(Event::create(events_so_far, &mut rng, &mut config));
+                                    events_so_far += worker.peers();
+                                }
+                                let epoch = migration_ns as usize + count;
+                                let moves: Vec<_> = if peers > 1 {
+                                    (0..BINS)
+                                        .step_by(2)
+                                        .map(|bin| {
+                                            owners[bin] = (owners[bin] + 1) % peers;
+                                            (bin, owners[bin])
+                                        })
+                                        .collect()
+                                } else {
+                                    Vec::new()
+                                };
+                                migrating.push(MigrationMetrics {
+                                    at_ns: migration_ns,
+                                    bins: moves.len(),
+                                    duration_ns: 0,
+                                });
+                                control_input.send(Control { epoch, moves });
+                                input.advance_to(epoch);
+                                control_input.advance_to(epoch);
+                            }
+                        }
+                        _ => break,
+                    }
+                }
+
                 if let Some(it) = input_times_gen.iter_until(target_ns) {
                     let input = input.as_mut().unwrap();
                     for _t in it {
@@ -817,8 +1918,10 @@ fn main() {
                         events_so_far += worker.peers();
                     }
                     input.advance_to(target_ns as usize + count);
+                    control_input.as_mut().unwrap().advance_to(target_ns as usize + count);
                 } else {
                     input.take().unwrap();
+                    control_input.take().unwrap();
                 }
 
                 if input.is_some() {
@@ -830,14 +1933,31 @@ fn main() {
                 }
             }
 
-            output_metric_collector.into_inner()
+            (output_metric_collector.into_inner(), (recovery, migrations))
         },
     )
     .expect("unsuccessful execution")
     .join()
     .into_iter()
     .map(|x| x.unwrap())
-    .collect();
+    .unzip();
+
+    let (recoveries, migrations): (Vec<_>, Vec<_>) = metrics.into_iter().unzip();
+
+    // Only runs with windowed queries or an allowed lateness report late events.
+    if report_late_events {
+        println!("late_events\t{}", late_events.load(Ordering::SeqCst));
+    }
+
+    let state_size_samples = match state_sizes_reporter {
+        Some((running, handle)) => {
+            running.store(false, Ordering::SeqCst);
+            handle.join().expect("state size reporter failed")
+        }
+        None => Vec::new(),
+    };
+
+    let exchange_records = record_counts_report.report();
 
     match statm_reporter_running {
         Some(statm_reporter_running) => statm_reporter_running.store(false, ::std::sync::atomic::Ordering::SeqCst),
@@ -891,12 +2011,59 @@ fn main() {
         }
     }
 
+    // Recovery from an injected failure, of the slowest worker of this process
+    let recovery_summary = recoveries
+        .into_iter()
+        .flatten()
+        .fold(None, |slowest: Option<RecoveryMetrics>, recovery| {
+            let slowest = slowest.unwrap_or_default();
+            Some(RecoveryMetrics {
+                downtime_ns: std::cmp::max(slowest.downtime_ns, recovery.downtime_ns),
+                catch_up_ns: std::cmp::max(slowest.catch_up_ns, recovery.catch_up_ns),
+                latency_spike_ns: std::cmp::max(slowest.latency_spike_ns, recovery.latency_spike_ns),
+            })
+        })
+        .map(|recovery| {
+            format!(
+                "recovery_downtime\t{}\nrecovery_catch_up\t{}\nrecovery_latency_spike\t{}\n",
+                recovery.downtime_ns, recovery.catch_up_ns, recovery.latency_spike_ns
+            )
+        });
+
+    // Migrations of key bins, until the slowest worker of this process processed their epoch
+    let mut slowest_migrations: BTreeMap<u64, MigrationMetrics> = BTreeMap::new();
+    for migration in migrations.into_iter().flatten() {
+        let slowest = slowest_migrations.entry(migration.at_ns).or_insert(migration);
+        slowest.duration_ns = std::cmp::max(slowest.duration_ns, migration.duration_ns);
+    }
+    let migration_summary: Vec<String> = slowest_migrations
+        .values()
+        .map(|migration| {
+            format!(
+                "migration\t{}\t{}\t{}",
+                migration.at_ns, migration.bins, migration.duration_ns
+            )
+        })
+        .collect();
+
     if let Some(output_file) = timeline_output {
         let mut f = File::create(output_file).expect("Cannot open timeline output file");
         f.write(::streaming_harness::format::format_summary_timeline(
                 "summary_timeline".to_string(),
                 timeline.clone()
             ).as_bytes());
+        if let Some(recovery_summary) = recovery_summary {
+            f.write(format!("\n{}", recovery_summary.trim_end()).as_bytes());
+        }
+        for migration in migration_summary.iter() {
+            f.write(format!("\n{}", migration).as_bytes());
+        }
+        for sample in state_size_samples.iter() {
+            f.write(format!("\n{}", sample).as_bytes());
+        }
+        for records in exchange_records.iter() {
+            f.write(format!("\n{}", records).as_bytes());
+        }
     } else {
         println!(
             "{}",
@@ -905,5 +2072,17 @@ fn main() {
                 timeline.clone()
             )
         );
+        if let Some(recovery_summary) = recovery_summary {
+            print!("{}", recovery_summary);
+        }
+        for migration in migration_summary.iter() {
+            println!("{}", migration);
+        }
+        for sample in state_size_samples.iter() {
+            println!("{}", sample);
+        }
+        for records in exchange_records.iter() {
+            println!("{}", records);
+        }
     }
 }
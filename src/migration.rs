@@ -0,0 +1,428 @@
+//! Live migration of keyed state between workers, after Megaphone.
+//!
+//! Keys are hashed into `BINS` bins, and a routing table assigns each bin to a worker. A stream
+//! of `Control` commands moves bins to other workers from a given epoch on. A stateful operator
+//! is fed by routers, which hold records back until the routing table of their time is final,
+//! and send each record to the worker owning its bin, tagged with the epoch the bin last moved
+//! at. The routers and the stateful operator of a worker share a `Migrator`.
+//!
+//! A bin moves at epoch `T` once its old owner processed all records before `T`: the stateful
+//! operator extracts the bin's state and hands it to its first router, which sends it to the
+//! new owner at time `T`. The router holds a capability for `T` until then, so the new owner's
+//! input can't pass `T` before the state arrived. The new owner holds back the bin's records
+//! routed from `T` on until it installed the state, see `Held`. The stateful operator finds the
+//! keys of a bin in a `BinIndex`.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::rc::Rc;
+
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::{Capability, Operator};
+use timely::dataflow::{Scope, Stream};
+use timely::scheduling::{Activator, Scheduler};
+use timely::state::primitives::ManagedMap;
+use timely::{Data, ExchangeData};
+
+use crate::primitives::ManagedMultiMap;
+
+/// Number of bits of a key's hash that select its bin.
+const BIN_SHIFT: usize = 8;
+
+/// Number of bins keys are hashed into.
+pub const BINS: usize = 1 << BIN_SHIFT;
+
+/// Smallest number of writes to the keys of a bin before its list is compacted.
+const MIN_COMPACTION: usize = 64;
+
+/// The bin of `key`.
+pub fn bin_of(key: u64) -> usize {
+    (key.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (64 - BIN_SHIFT)) as usize
+}
+
+/// The worker owning `bin` before any migration.
+pub fn initial_owner(bin: usize, peers: usize) -> usize {
+    bin % peers
+}
+
+/// Moves bins to other workers, for the records from `epoch` on.
+#[derive(Clone, Debug, Serialize, Deserialize, Abomonation)]
+pub struct Control {
+    pub epoch: usize,
+    /// Stores (bin, worker it moves to)
+    pub moves: Vec<(usize, usize)>,
+}
+
+/// The routing table of a migrator, and the epochs of the bins' state at its worker, for
+/// checkpoints.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Routing {
+    history: Vec<Vec<(usize, usize)>>,
+    installed: Vec<usize>,
+}
+
+/// A record, or the state of a bin, routed to the worker owning the bin.
+#[derive(Clone, Abomonation)]
+pub enum Routed<D, B> {
+    /// A record of a bin, with the epoch the bin last moved at.
+    Record(usize, usize, D),
+    /// The state of a bin, moving at an epoch.
+    Bin(usize, usize, B),
+}
+
+struct Shared<B> {
+    worker: usize,
+    /// Stores bin -> (epoch, owner from that epoch on), in epoch order
+    history: Vec<Vec<(usize, usize)>>,
+    /// Epochs of the controls applied, as each router of the worker applies them
+    applied: BTreeSet<usize>,
+    /// Stores bin -> epoch of the bin's state at this worker
+    installed: Vec<usize>,
+    /// Bins leaving this worker that are yet to be extracted, as (epoch, bin)
+    leaving: BTreeSet<(usize, usize)>,
+    /// Extracted bins yet to be sent, as (epoch, bin, new owner, state)
+    outbox: Vec<(usize, usize, usize, B)>,
+    /// Whether a router of the migrator sends the extracted bins
+    sending: bool,
+    /// Activator of the router sending the extracted bins
+    router: Option<Activator>,
+}
+
+impl<B> Shared<B> {
+    /// The epoch `bin` last moved at, and its owner, at `time`.
+    fn owner(&self, bin: usize, time: usize) -> (usize, usize) {
+        *self.history[bin]
+            .iter()
+            .rev()
+            .find(|(epoch, _)| *epoch <= time)
+            .unwrap()
+    }
+
+    /// Applies `control`, returning whether bins leave this worker.
+    fn apply(&mut self, control: &Control) -> bool {
+        if !self.applied.insert(control.epoch) {
+            return self.is_leaving(control.epoch);
+        }
+        let mut leaving = false;
+        for &(bin, worker) in control.moves.iter() {
+            let (_, owner) = self.owner(bin, control.epoch);
+            if owner == worker {
+                continue;
+            }
+            assert!(
+                self.history[bin].last().unwrap().0 < control.epoch,
+                "controls must be applied in epoch order"
+            );
+            self.history[bin].push((control.epoch, worker));
+            if owner == self.worker {
+                self.leaving.insert((control.epoch, bin));
+                leaving = true;
+            }
+        }
+        leaving
+    }
+
+    /// Whether bins leaving at `epoch` are yet to be extracted or sent.
+    fn is_leaving(&self, epoch: usize) -> bool {
+        self.leaving
+            .range((epoch, 0)..=(epoch, BINS))
+            .next()
+            .is_some()
+            || self.outbox.iter().any(|(e, _, _, _)| *e == epoch)
+    }
+}
+
+/// The migration state of one stateful operator of a worker, shared with its routers.
+pub struct Migrator<B>(Rc<RefCell<Shared<B>>>);
+
+impl<B> Clone for Migrator<B> {
+    fn clone(&self) -> Self {
+        Migrator(self.0.clone())
+    }
+}
+
+impl<B: ExchangeData> Migrator<B> {
+    pub fn new(worker: usize, peers: usize) -> Self {
+        Migrator(Rc::new(RefCell::new(Shared {
+            worker,
+            history: (0..BINS)
+                .map(|bin| vec![(0, initial_owner(bin, peers))])
+                .collect(),
+            applied: BTreeSet::new(),
+            installed: vec![0; BINS],
+            leaving: BTreeSet::new(),
+            outbox: Vec::new(),
+            sending: false,
+            router: None,
+        })))
+    }
+
+    /// Routes each record of `stream` to the worker owning the bin of its `key`, following the
+    /// moves of `control`. The stateful operator exchanges the routed records by their worker.
+    ///
+    /// The first router of a migrator also sends the bins extracted by the stateful operator.
+    pub fn route<S, D, K>(
+        &self,
+        stream: &Stream<S, D>,
+        control: &Stream<S, Control>,
+        name: &str,
+        key: K,
+    ) -> Stream<S, (usize, Routed<D, B>)>
+    where
+        S: Scope<Timestamp = usize>,
+        D: Data,
+        K: Fn(&D) -> u64 + 'static,
+    {
+        let shared = self.0.clone();
+        let scope = stream.scope();
+        let sends_bins = !std::mem::replace(&mut shared.borrow_mut().sending, true);
+
+        stream.binary_frontier(
+            control,
+            Pipeline,
+            Pipeline,
+            name,
+            move |_capability, info, _| {
+                if sends_bins {
+                    shared.borrow_mut().router = Some(scope.activator_for(&info.address[..]));
+                }
+                // Stores time -> (capability, records) of records waiting for their routing table
+                let mut pending: BTreeMap<usize, (Capability<usize>, Vec<D>)> = BTreeMap::new();
+                // Stores epoch -> capability held until the bins leaving at the epoch are sent
+                let mut holds: BTreeMap<usize, Capability<usize>> = BTreeMap::new();
+                let mut buffer = Vec::new();
+
+                move |input, control, output| {
+                    control.for_each(|time, data| {
+                        for control in data.iter() {
+                            assert!(*time.time() <= control.epoch, "control for a past epoch");
+                            let leaving = shared.borrow_mut().apply(control);
+                            if sends_bins && leaving && !holds.contains_key(&control.epoch) {
+                                holds.insert(control.epoch, time.delayed(&control.epoch));
+                            }
+                        }
+                    });
+
+                    input.for_each(|time, data| {
+                        data.swap(&mut buffer);
+                        pending
+                            .entry(*time.time())
+                            .or_insert_with(|| (time.retain(), Vec::new()))
+                            .1
+                            .extend(buffer.drain(..));
+                    });
+
+                    // Route the records whose routing table no control can change anymore.
+                    let complete = control
+                        .frontier()
+                        .frontier()
+                        .get(0)
+                        .cloned()
+                        .unwrap_or(usize::max_value());
+                    let later = pending.split_off(&complete);
+                    for (time, (capability, records)) in std::mem::replace(&mut pending, later) {
+                        let shared = shared.borrow();
+                        let mut session = output.session(&capability);
+                        for record in records.into_iter() {
+                            let bin = bin_of(key(&record));
+                            let (epoch, owner) = shared.owner(bin, time);
+                            session.give((owner, Routed::Record(bin, epoch, record)));
+                        }
+                    }
+
+                    // Send the bins extracted by the stateful operator to their new owners.
+                    if sends_bins {
+                        let outbox = std::mem::replace(&mut shared.borrow_mut().outbox, Vec::new());
+                        for (epoch, bin, owner, state) in outbox.into_iter() {
+                            let capability = &holds[&epoch];
+                            output
+                                .session(capability)
+                                .give((owner, Routed::Bin(bin, epoch, state)));
+                        }
+                        let shared = shared.borrow();
+                        holds.retain(|epoch, _| shared.is_leaving(*epoch));
+                    }
+                }
+            },
+        )
+    }
+
+    /// The routing of the bins, for checkpoints. The stateful operator captures it once its
+    /// input is complete up to the checkpoint, when the bins that moved before are installed.
+    pub fn routing(&self) -> Routing {
+        let shared = self.0.borrow();
+        Routing {
+            history: shared.history.clone(),
+            installed: shared.installed.clone(),
+        }
+    }
+
+    /// Restores the routing captured by `routing`, as if its controls had been applied.
+    pub fn restore(&self, routing: Routing) {
+        let mut shared = self.0.borrow_mut();
+        shared.applied = routing
+            .history
+            .iter()
+            .flat_map(|moves| moves.iter().skip(1).map(|(epoch, _)| *epoch))
+            .collect();
+        shared.history = routing.history;
+        shared.installed = routing.installed;
+    }
+
+    /// Whether this worker holds the state of `bin` for records routed at `epoch`.
+    pub fn is_installed(&self, bin: usize, epoch: usize) -> bool {
+        self.0.borrow().installed[bin] >= epoch
+    }
+
+    /// Records that the state of `bin`, moving at `epoch`, was installed at this worker.
+    pub fn install(&self, bin: usize, epoch: usize) {
+        self.0.borrow_mut().installed[bin] = epoch;
+    }
+
+    /// The bins leaving this worker at epochs up to `complete`, as (epoch, bin), which the
+    /// stateful operator extracts once it processed all records before `complete`.
+    pub fn leaving(&self, complete: usize) -> Vec<(usize, usize)> {
+        let shared = self.0.borrow();
+        shared
+            .leaving
+            .range(..(complete.saturating_add(1), 0))
+            .cloned()
+            .collect()
+    }
+
+    /// Hands the extracted `state` of `bin`, leaving at `epoch`, to the router to send it.
+    pub fn extracted(&self, epoch: usize, bin: usize, state: B) {
+        let mut shared = self.0.borrow_mut();
+        shared.leaving.remove(&(epoch, bin));
+        let (_, owner) = shared.owner(bin, epoch);
+        shared.outbox.push((epoch, bin, owner, state));
+        if let Some(ref router) = shared.router {
+            router.activate();
+        }
+    }
+}
+
+/// Records of the two inputs of a stateful operator whose bins are yet to be installed, held
+/// with capabilities for their times until the bins' state arrives.
+pub struct Held<D1, D2> {
+    /// Stores bin -> held records of each input
+    bins: HashMap<usize, (Vec<(Capability<usize>, D1)>, Vec<(Capability<usize>, D2)>)>,
+}
+
+impl<D1, D2> Held<D1, D2> {
+    pub fn new() -> Self {
+        Held {
+            bins: HashMap::new(),
+        }
+    }
+
+    /// Holds `record` of the first input, of `bin`, at the time of `capability`.
+    pub fn hold1(&mut self, bin: usize, capability: &Capability<usize>, record: D1) {
+        let held = &mut self.bins.entry(bin).or_insert_with(Default::default).0;
+        held.push((capability.clone(), record));
+    }
+
+    /// Holds `record` of the second input, of `bin`, at the time of `capability`.
+    pub fn hold2(&mut self, bin: usize, capability: &Capability<usize>, record: D2) {
+        let held = &mut self.bins.entry(bin).or_insert_with(Default::default).1;
+        held.push((capability.clone(), record));
+    }
+
+    /// Removes and returns the held records of `bin`, in the order they arrived per input.
+    pub fn release(
+        &mut self,
+        bin: usize,
+    ) -> (Vec<(Capability<usize>, D1)>, Vec<(Capability<usize>, D2)>) {
+        self.bins.remove(&bin).unwrap_or_default()
+    }
+}
+
+/// The keys with state in each bin of a stateful operator, to find the state of a bin when it
+/// leaves the worker.
+///
+/// The keys are kept in a managed multimap from bin to keys, which appends keys without reading
+/// the bin's list, so a list may repeat keys and hold keys whose state is gone. Memory holds only
+/// two counters per bin, and a bin's list is compacted once the keys added and removed since it
+/// was last written outnumber its length.
+pub struct BinIndex {
+    /// Stores bin -> keys with state in the bin, possibly repeated or stale
+    keys: ManagedMultiMap<usize, usize>,
+    /// Stores bin -> (length of the list when last written, keys added or removed since)
+    counts: Vec<(usize, usize)>,
+}
+
+impl BinIndex {
+    pub fn new(map: Box<ManagedMap<usize, Vec<usize>>>) -> Self {
+        BinIndex {
+            keys: ManagedMultiMap::new(map),
+            counts: vec![(0, 0); BINS],
+        }
+    }
+
+    /// Records that `key`, of `bin`, has state, compacting the bin's list when it grew too long.
+    pub fn insert<F: FnMut(usize) -> bool>(&mut self, bin: usize, key: usize, has_state: F) {
+        self.keys.append(bin, key);
+        self.written(bin, has_state);
+    }
+
+    /// Records that a key of `bin` lost its state, compacting the bin's list when too many of its
+    /// keys are stale.
+    pub fn remove<F: FnMut(usize) -> bool>(&mut self, bin: usize, has_state: F) {
+        self.written(bin, has_state);
+    }
+
+    /// The distinct keys of `bin` for which `has_state` holds, rewriting the bin's list to them.
+    pub fn keys<F: FnMut(usize) -> bool>(&mut self, bin: usize, mut has_state: F) -> Vec<usize> {
+        let mut keys = self.keys.remove(&bin).unwrap_or_default();
+        keys.sort();
+        keys.dedup();
+        keys.retain(|key| has_state(*key));
+        self.counts[bin] = (keys.len(), 0);
+        self.keys.replace(bin, keys.clone());
+        keys
+    }
+
+    /// Removes and returns the distinct keys of `bin`, some of which may have no state.
+    pub fn take(&mut self, bin: usize) -> Vec<usize> {
+        let mut keys = self.keys.remove(&bin).unwrap_or_default();
+        keys.sort();
+        keys.dedup();
+        self.counts[bin] = (0, 0);
+        keys
+    }
+
+    fn written<F: FnMut(usize) -> bool>(&mut self, bin: usize, has_state: F) {
+        let (length, writes) = &mut self.counts[bin];
+        *writes += 1;
+        if *writes > std::cmp::max(*length, MIN_COMPACTION) {
+            self.keys(bin, has_state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{bin_of, initial_owner, BINS};
+
+    #[test]
+    fn consecutive_keys_spread_over_all_bins() {
+        let bins: HashSet<usize> = (0..10_000).map(bin_of).collect();
+        assert!(bins.iter().all(|bin| *bin < BINS));
+        assert_eq!(bins.len(), BINS);
+    }
+
+    #[test]
+    fn initial_owners_hold_the_bins_evenly() {
+        for peers in 1..=5 {
+            let mut bins = vec![0; peers];
+            for bin in 0..BINS {
+                bins[initial_owner(bin, peers)] += 1;
+            }
+            let most = bins.iter().max().unwrap();
+            let fewest = bins.iter().min().unwrap();
+            assert!(most - fewest <= 1, "uneven bins {:?}", bins);
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::rc::Rc;
+
+use faster_rs::FasterRmw;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use timely::state::primitives::ManagedMap;
+
+/// Reads of the values of a batch of keys, and writes of the values the batch changed.
+///
+/// Operators read the keys of a whole input batch with `Batch::read`, access and update their
+/// values in memory while processing the batch, and write the values they changed back when the
+/// batch is dropped. Each key is read and written at most once per batch, however many records
+/// of the batch touch it. Only `insert`, `update` and `remove` mark a key as changed, so keys
+/// that are only read are not written back.
+///
+/// No backend batches these calls natively yet: the managed map interface has no multi-key
+/// calls, so every read and write is still one backend call, and this only saves the repeated
+/// calls for keys that several records of a batch touch. RocksDB is the backend expected to get
+/// native batching, through its `MultiGet` and write batches; FASTER has no multi-key operations.
+/// Values are kept as the backend returns them, and copied only when updated.
+pub struct Batch<'a, K, V>
+where
+    K: 'static + Eq + Hash + Clone + Serialize + DeserializeOwned,
+    V: 'static + Clone + FasterRmw + Serialize + DeserializeOwned,
+{
+    map: &'a mut Box<ManagedMap<K, V>>,
+    /// Stores key -> value, `None` if the key has no value
+    values: HashMap<K, Option<Rc<V>>>,
+    /// Keys whose values the batch wrote
+    changed: HashSet<K>,
+}
+
+impl<'a, K, V> Batch<'a, K, V>
+where
+    K: 'static + Eq + Hash + Clone + Serialize + DeserializeOwned,
+    V: 'static + Clone + FasterRmw + Serialize + DeserializeOwned,
+{
+    /// Reads the values of the distinct `keys` from `map`.
+    pub fn read<I: IntoIterator<Item = K>>(map: &'a mut Box<ManagedMap<K, V>>, keys: I) -> Self {
+        let mut values = HashMap::new();
+        for key in keys {
+            if !values.contains_key(&key) {
+                let value = map.get(&key);
+                values.insert(key, value);
+            }
+        }
+        Batch {
+            map,
+            values,
+            changed: HashSet::new(),
+        }
+    }
+
+    /// The value of `key`, read from the map if the batch didn't read it.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.load(key).as_ref().map(|value| &**value)
+    }
+
+    /// Updates the value of `key` in place with `update`, returning whether the key has a value.
+    pub fn update<F: FnOnce(&mut V)>(&mut self, key: &K, update: F) -> bool {
+        if self.load(key).is_none() {
+            return false;
+        }
+        self.changed.insert(key.clone());
+        let value = self.values.get_mut(key).unwrap().as_mut().unwrap();
+        update(Rc::make_mut(value));
+        true
+    }
+
+    /// Updates the value of `key` in place with `update`, setting it to `default()` first if the
+    /// key has none.
+    pub fn update_or_insert_with<D, F>(&mut self, key: K, default: D, update: F)
+    where
+        D: FnOnce() -> V,
+        F: FnOnce(&mut V),
+    {
+        self.changed.insert(key.clone());
+        let value = self.load(&key);
+        update(Rc::make_mut(
+            value.get_or_insert_with(|| Rc::new(default())),
+        ));
+    }
+
+    /// Sets the value of `key`.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.changed.insert(key.clone());
+        self.values.insert(key, Some(Rc::new(value)));
+    }
+
+    /// Removes and returns the value of `key`.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let removed = self.load(key).take();
+        if removed.is_some() {
+            self.changed.insert(key.clone());
+        }
+        removed.map(unwrap)
+    }
+
+    fn load(&mut self, key: &K) -> &mut Option<Rc<V>> {
+        if !self.values.contains_key(key) {
+            let value = self.map.get(key);
+            self.values.insert(key.clone(), value);
+        }
+        self.values.get_mut(key).unwrap()
+    }
+}
+
+impl<'a, K, V> Drop for Batch<'a, K, V>
+where
+    K: 'static + Eq + Hash + Clone + Serialize + DeserializeOwned,
+    V: 'static + Clone + FasterRmw + Serialize + DeserializeOwned,
+{
+    /// Writes the values the batch changed back to the map, removing the keys without a value.
+    fn drop(&mut self) {
+        for key in self.changed.drain() {
+            match self.values.remove(&key).and_then(|value| value) {
+                Some(value) => self.map.insert(key, unwrap(value)),
+                None => {
+                    self.map.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+/// The value of `value`, copied only if the backend still shares it.
+fn unwrap<V: Clone>(value: Rc<V>) -> V {
+    Rc::try_unwrap(value).unwrap_or_else(|value| (*value).clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use timely::state::primitives::ManagedMap;
+
+    use super::Batch;
+
+    #[test]
+    fn drop_writes_back_only_the_changed_keys() {
+        with_managed_state!(state_handle, {
+            let mut map: Box<ManagedMap<usize, Vec<usize>>> = state_handle.get_managed_map("map");
+            map.insert(1, vec![1]);
+            map.insert(2, vec![2]);
+            map.insert(3, vec![3]);
+            {
+                let mut batch = Batch::read(&mut map, vec![1, 2, 3, 4]);
+                assert_eq!(batch.get(&1), Some(&vec![1]));
+                assert!(batch.update(&2, |values| values.push(20)));
+                assert!(!batch.update(&4, |values| values.push(40)));
+                assert_eq!(batch.remove(&3), Some(vec![3]));
+                batch.update_or_insert_with(5, Vec::new, |values| values.push(5));
+                // Nothing is written before the batch is dropped.
+                assert_eq!(
+                    batch.map.get(&2).map(|values| (*values).clone()),
+                    Some(vec![2])
+                );
+            }
+            let mut value = |key| map.get(&key).map(|values| (*values).clone());
+            assert_eq!(value(1), Some(vec![1]));
+            assert_eq!(value(2), Some(vec![2, 20]));
+            assert_eq!(value(3), None);
+            assert_eq!(value(4), None);
+            assert_eq!(value(5), Some(vec![5]));
+        });
+    }
+}
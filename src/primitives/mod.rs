@@ -0,0 +1,37 @@
+//! State primitives built on the managed maps of the state backends.
+
+/// Runs `$body` in the constructor of an operator of a one-worker dataflow, with `$state` bound
+/// to the operator's state handle, whose managed maps are kept by the in-memory backend.
+#[cfg(test)]
+macro_rules! with_managed_state {
+    ($state:ident, $body:block) => {{
+        use timely::dataflow::channels::pact::Pipeline;
+        use timely::dataflow::operators::{Operator, ToStream};
+        use timely::dataflow::Stream;
+        use timely::state::backends::InMemoryBackend;
+
+        timely::execute_from_args(std::iter::empty::<String>(), |worker, _| {
+            worker.dataflow::<usize, _, _, InMemoryBackend>(|scope, _| {
+                let _: Stream<_, ()> = (0..0usize).to_stream(scope).unary(
+                    Pipeline,
+                    "Test",
+                    |_capability, _info, $state| {
+                        $body;
+                        |_input, _output| {}
+                    },
+                );
+            });
+        })
+        .expect("couldn't run the test dataflow");
+    }};
+}
+
+mod batch;
+mod multimap;
+mod ordered;
+mod timers;
+
+pub use self::batch::Batch;
+pub use self::multimap::ManagedMultiMap;
+pub use self::ordered::{BucketKeys, ManagedOrderedMap};
+pub use self::timers::ManagedTimers;
@@ -0,0 +1,69 @@
+use std::hash::Hash;
+use std::rc::Rc;
+
+use faster_rs::FasterRmw;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use timely::state::primitives::ManagedMap;
+
+use super::Batch;
+
+/// A managed map from keys to lists of values, which appends values without reading the list.
+///
+/// Appends go through the backend's read-modify-write, merging into the stored list within the
+/// backend where it supports merges, such as FASTER's RMW and RocksDB's merge operator, instead
+/// of reading and rewriting the whole list.
+pub struct ManagedMultiMap<K, V>
+where
+    K: 'static + Eq + Hash + Clone + Serialize + DeserializeOwned,
+    V: 'static + Clone + Serialize + DeserializeOwned,
+{
+    map: Box<ManagedMap<K, Vec<V>>>,
+}
+
+impl<K, V> ManagedMultiMap<K, V>
+where
+    K: 'static + Eq + Hash + Clone + Serialize + DeserializeOwned,
+    V: 'static + Clone + Serialize + DeserializeOwned,
+    Vec<V>: FasterRmw,
+{
+    pub fn new(map: Box<ManagedMap<K, Vec<V>>>) -> Self {
+        ManagedMultiMap { map }
+    }
+
+    /// Appends `value` to the list of `key`.
+    pub fn append(&mut self, key: K, value: V) {
+        self.map.rmw(key, vec![value]);
+    }
+
+    /// Appends `values` to the list of `key`.
+    pub fn extend(&mut self, key: K, values: Vec<V>) {
+        if !values.is_empty() {
+            self.map.rmw(key, values);
+        }
+    }
+
+    /// The list of `key`, if it has one.
+    pub fn get(&mut self, key: &K) -> Option<Rc<Vec<V>>> {
+        self.map.get(key)
+    }
+
+    /// Reads the lists of `keys` for a batch, see `Batch`.
+    pub fn batch<I: IntoIterator<Item = K>>(&mut self, keys: I) -> Batch<K, Vec<V>> {
+        Batch::read(&mut self.map, keys)
+    }
+
+    /// Removes and returns the list of `key`.
+    pub fn remove(&mut self, key: &K) -> Option<Vec<V>> {
+        self.map.remove(key)
+    }
+
+    /// Replaces the list of `key` with `values`, removing it if `values` is empty.
+    pub fn replace(&mut self, key: K, values: Vec<V>) {
+        if values.is_empty() {
+            self.map.remove(&key);
+        } else {
+            self.map.insert(key, values);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::hash::Hash;
+use std::ops::{Bound, RangeBounds};
+use std::rc::Rc;
+
+use faster_rs::FasterRmw;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use timely::state::primitives::ManagedMap;
+
+/// The keys of a bucket of a `ManagedOrderedMap`, in order, which merge as a set union.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BucketKeys<K>(Vec<K>);
+
+impl<K: Ord + Clone + Serialize + DeserializeOwned> FasterRmw for BucketKeys<K> {
+    fn rmw(&self, modification: Self) -> Self {
+        let mut keys = self.0.clone();
+        keys.extend(modification.0);
+        keys.sort();
+        keys.dedup();
+        BucketKeys(keys)
+    }
+}
+
+/// A managed map that also iterates and removes the keys in a range, in key order.
+///
+/// The backends' managed maps only look up single keys, so the map indexes its keys in a second
+/// managed map, by buckets of `granularity` consecutive positions. Ranges are resolved bucket by
+/// bucket, and only the keys present are looked up, so gaps within a bucket cost nothing.
+///
+/// Memory holds only the first and last bucket that may hold keys, which bound open-ended
+/// ranges, and the key indexed last. These bounds are not part of the backend's state: a map
+/// created over a backend that already holds entries doesn't find them in ranges until they are
+/// written again, as restoring a checkpoint does.
+pub struct ManagedOrderedMap<K, V> {
+    map: Box<ManagedMap<K, V>>,
+    /// Stores bucket -> keys with a value in the bucket
+    buckets: Box<ManagedMap<usize, BucketKeys<K>>>,
+    /// The position of a key, which orders keys
+    position: fn(&K) -> usize,
+    granularity: usize,
+    /// The first and last bucket that may hold keys, if any
+    bounds: Option<(usize, usize)>,
+    /// The key indexed last, whose index entry need not be written again
+    indexed: Option<K>,
+}
+
+impl<V> ManagedOrderedMap<usize, V>
+where
+    V: 'static + FasterRmw + Serialize + DeserializeOwned,
+{
+    pub fn new(
+        map: Box<ManagedMap<usize, V>>,
+        buckets: Box<ManagedMap<usize, BucketKeys<usize>>>,
+        granularity: usize,
+    ) -> Self {
+        assert!(granularity > 0, "bucket granularity must be positive");
+        ManagedOrderedMap {
+            map,
+            buckets,
+            position: |key| *key,
+            granularity,
+            bounds: None,
+            indexed: None,
+        }
+    }
+}
+
+impl<K, V> ManagedOrderedMap<K, V>
+where
+    K: 'static + Ord + Eq + Hash + Clone + Serialize + DeserializeOwned,
+    V: 'static + FasterRmw + Serialize + DeserializeOwned,
+{
+    pub fn get(&mut self, key: &K) -> Option<Rc<V>> {
+        self.map.get(key)
+    }
+
+    pub fn contains_key(&mut self, key: &K) -> bool {
+        self.map.get(key).is_some()
+    }
+
+    /// The smallest key with a value.
+    pub fn first_key(&mut self) -> Option<K> {
+        let (first, last) = self.bounds?;
+        for bucket in first..=last {
+            if let Some(keys) = self.buckets.get(&bucket) {
+                if let Some(key) = keys.0.iter().next() {
+                    self.bounds = Some((bucket, last));
+                    return Some(key.clone());
+                }
+            }
+        }
+        self.bounds = None;
+        None
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.index(&key);
+        self.map.insert(key, value);
+    }
+
+    /// Merges `modification` into the value of `key` with the backend's read-modify-write.
+    pub fn rmw(&mut self, key: K, modification: V) {
+        self.index(&key);
+        self.map.rmw(key, modification);
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let bucket = self.bucket(key);
+        if let Some(keys) = self.buckets.remove(&bucket) {
+            let keys: Vec<K> = keys.0.into_iter().filter(|other| other != key).collect();
+            if !keys.is_empty() {
+                self.buckets.insert(bucket, BucketKeys(keys));
+            }
+        }
+        if self.indexed.as_ref() == Some(key) {
+            self.indexed = None;
+        }
+        self.map.remove(key)
+    }
+
+    /// The entries with keys in `range`, in key order.
+    pub fn range<R: RangeBounds<K>>(&mut self, range: R) -> Vec<(K, Rc<V>)> {
+        let mut entries = Vec::new();
+        for bucket in self.buckets_of(&range) {
+            let keys = match self.buckets.get(&bucket) {
+                Some(keys) => keys,
+                None => continue,
+            };
+            for key in keys.0.iter().filter(|key| range.contains(key)) {
+                if let Some(value) = self.map.get(key) {
+                    entries.push((key.clone(), value));
+                }
+            }
+        }
+        entries
+    }
+
+    /// Removes and returns the entries with keys in `range`, in key order.
+    pub fn remove_range<R: RangeBounds<K>>(&mut self, range: R) -> Vec<(K, V)> {
+        let mut removed = Vec::new();
+        for bucket in self.buckets_of(&range) {
+            let keys = match self.buckets.remove(&bucket) {
+                Some(keys) => keys,
+                None => continue,
+            };
+            let (within, beyond): (Vec<K>, Vec<K>) =
+                keys.0.into_iter().partition(|key| range.contains(key));
+            if !beyond.is_empty() {
+                self.buckets.insert(bucket, BucketKeys(beyond));
+            }
+            for key in within.into_iter() {
+                if self.indexed.as_ref() == Some(&key) {
+                    self.indexed = None;
+                }
+                if let Some(value) = self.map.remove(&key) {
+                    removed.push((key, value));
+                }
+            }
+        }
+        removed
+    }
+
+    fn bucket(&self, key: &K) -> usize {
+        (self.position)(key) / self.granularity
+    }
+
+    /// Adds `key` to the index of its bucket, unless it was the key indexed last.
+    fn index(&mut self, key: &K) {
+        if self.indexed.as_ref() == Some(key) {
+            return;
+        }
+        let bucket = self.bucket(key);
+        self.buckets.rmw(bucket, BucketKeys(vec![key.clone()]));
+        self.bounds = Some(match self.bounds {
+            Some((first, last)) => (first.min(bucket), last.max(bucket)),
+            None => (bucket, bucket),
+        });
+        self.indexed = Some(key.clone());
+    }
+
+    /// The buckets that may hold keys in `range`.
+    fn buckets_of<R: RangeBounds<K>>(&self, range: &R) -> std::ops::RangeInclusive<usize> {
+        let (first, last) = match self.bounds {
+            Some(bounds) => bounds,
+            None => return 1..=0,
+        };
+        let start = match range.start_bound() {
+            Bound::Included(key) | Bound::Excluded(key) => first.max(self.bucket(key)),
+            Bound::Unbounded => first,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(key) | Bound::Excluded(key) => last.min(self.bucket(key)),
+            Bound::Unbounded => last,
+        };
+        start..=end
+    }
+}
@@ -0,0 +1,115 @@
+use faster_rs::FasterRmw;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use timely::state::primitives::ManagedMap;
+
+use super::ordered::BucketKeys;
+use super::ManagedOrderedMap;
+
+/// Number of slots per bucket of the slots' index.
+const SLOTS_PER_BUCKET: usize = 1024;
+
+/// Event-time timers kept in managed maps of the state backend.
+///
+/// Timers are grouped into slots of `granularity` timestamps, and each slot's timers are stored
+/// as one value of the backend. The slots are indexed in a second managed map, see
+/// `ManagedOrderedMap`, so the memory used by timers doesn't grow with their number or span.
+pub struct ManagedTimers<T> {
+    /// Stores slot -> (time, timer) of the timers set for times in the slot
+    slots: ManagedOrderedMap<usize, Vec<(usize, T)>>,
+    granularity: usize,
+}
+
+impl<T> ManagedTimers<T>
+where
+    T: 'static + Eq + Clone + Serialize + DeserializeOwned,
+    Vec<(usize, T)>: FasterRmw,
+{
+    pub fn new(
+        map: Box<ManagedMap<usize, Vec<(usize, T)>>>,
+        index: Box<ManagedMap<usize, BucketKeys<usize>>>,
+        granularity: usize,
+    ) -> Self {
+        assert!(granularity > 0, "timer granularity must be positive");
+        ManagedTimers {
+            slots: ManagedOrderedMap::new(map, index, SLOTS_PER_BUCKET),
+            granularity,
+        }
+    }
+
+    /// Sets `timer` to fire at `time`.
+    pub fn register(&mut self, time: usize, timer: T) {
+        self.slots.rmw(time / self.granularity, vec![(time, timer)]);
+    }
+
+    /// Cancels `timer` set for `time`, returning whether it was set.
+    pub fn cancel(&mut self, time: usize, timer: &T) -> bool {
+        let slot = time / self.granularity;
+        match self.slots.remove(&slot) {
+            Some(mut timers) => {
+                let count = timers.len();
+                timers.retain(|(t, other)| *t != time || other != timer);
+                let cancelled = timers.len() < count;
+                if !timers.is_empty() {
+                    self.slots.insert(slot, timers);
+                }
+                cancelled
+            }
+            None => false,
+        }
+    }
+
+    /// Removes and returns the timers set for times before `frontier`, in time order.
+    pub fn fire(&mut self, frontier: usize) -> Vec<(usize, T)> {
+        if frontier == 0 {
+            return Vec::new();
+        }
+        let last = (frontier - 1) / self.granularity;
+        let mut fired = Vec::new();
+        for (slot, timers) in self.slots.remove_range(..=last) {
+            // Only the last slot can hold timers at or after the frontier.
+            let (due, pending): (Vec<_>, Vec<_>) =
+                timers.into_iter().partition(|(time, _)| *time < frontier);
+            fired.extend(due);
+            if !pending.is_empty() {
+                self.slots.insert(slot, pending);
+            }
+        }
+        fired.sort_by_key(|(time, _)| *time);
+        fired
+    }
+
+    /// A time no later than the earliest timer, if any timer is set.
+    pub fn lower_bound(&mut self) -> Option<usize> {
+        self.slots.first_key().map(|slot| slot * self.granularity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ManagedTimers;
+
+    #[test]
+    fn fire_removes_the_timers_before_the_frontier_in_time_order() {
+        with_managed_state!(state_handle, {
+            let mut timers: ManagedTimers<usize> = ManagedTimers::new(
+                state_handle.get_managed_map("timers"),
+                state_handle.get_managed_map("timers_index"),
+                10,
+            );
+            timers.register(25, 1);
+            timers.register(3, 2);
+            timers.register(20, 3);
+            timers.register(5_000, 4);
+
+            assert!(timers.fire(0).is_empty());
+            assert_eq!(timers.fire(21), vec![(3, 2), (20, 3)]);
+            // The timer at 25 shares its slot with the fired one at 20.
+            assert_eq!(timers.lower_bound(), Some(20));
+            assert!(timers.cancel(25, &1));
+            assert!(!timers.cancel(25, &1));
+            assert_eq!(timers.fire(6_000), vec![(5_000, 4)]);
+            assert_eq!(timers.lower_bound(), None);
+        });
+    }
+}
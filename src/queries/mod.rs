@@ -1,9 +1,14 @@
 use std::rc::Rc;
+use std::time::Instant;
+use streaming_harness::util::ToNanos;
 use timely::dataflow::operators::capture::event::link::EventLink;
 use timely::dataflow::operators::capture::Replay;
 use timely::dataflow::{Scope, Stream};
 
+use crate::checkpoint::Checkpoints;
 use crate::event::{Auction, Bid, Date, Person};
+use crate::migration::Control;
+use crate::state_sizes::StateSizes;
 
 mod q1;
 mod q2;
@@ -15,13 +20,33 @@ mod q4_managed;
 mod q4_q6_common;
 mod q4_q6_common_managed;
 mod q5;
-mod q5_managed;
 mod q6;
 mod q6_managed;
 mod q7;
-mod q7_managed;
 mod q8;
-mod q8_managed;
+mod q11;
+mod q12;
+mod q12_managed;
+mod q13;
+mod q13_managed;
+mod q14;
+mod q15;
+mod q15_managed;
+mod q15_q16_common;
+mod q16;
+mod q16_managed;
+mod q17;
+mod q17_managed;
+mod q18;
+mod q18_managed;
+mod q19;
+mod q19_managed;
+mod q20;
+mod q20_managed;
+mod q21;
+mod q22;
+
+mod partitioning;
 
 pub use self::q1::q1;
 pub use self::q2::q2;
@@ -33,13 +58,33 @@ pub use self::q4_managed::q4_managed;
 pub use self::q4_q6_common::q4_q6_common;
 pub use self::q4_q6_common_managed::q4_q6_common_managed;
 pub use self::q5::q5;
-pub use self::q5_managed::q5_managed;
 pub use self::q6::q6;
 pub use self::q6_managed::q6_managed;
 pub use self::q7::q7;
-pub use self::q7_managed::q7_managed;
 pub use self::q8::q8;
-pub use self::q8_managed::q8_managed;
+pub use self::q11::q11;
+pub use self::q12::q12;
+pub use self::q12_managed::q12_managed;
+pub use self::q13::{q13, SideInput};
+pub use self::q13_managed::q13_managed;
+pub use self::q14::{q14, BidTimeType};
+pub use self::q15::q15;
+pub use self::q15_managed::q15_managed;
+pub use self::q15_q16_common::DailyStatistics;
+pub use self::q16::q16;
+pub use self::q16_managed::q16_managed;
+pub use self::q17::{q17, PriceStatistics};
+pub use self::q17_managed::q17_managed;
+pub use self::q18::q18;
+pub use self::q18_managed::q18_managed;
+pub use self::q19::{q19, TopNEmission};
+pub use self::q19_managed::q19_managed;
+pub use self::q20::q20;
+pub use self::q20_managed::q20_managed;
+pub use self::q21::q21;
+pub use self::q22::q22;
+
+pub use self::partitioning::{HotKeys, Partitioner, Partitioning, RecordCounts};
 
 pub struct NexmarkInput<'a> {
     pub bids: &'a Rc<EventLink<usize, Bid>>,
@@ -47,6 +92,14 @@ pub struct NexmarkInput<'a> {
     pub people: &'a Rc<EventLink<usize, Person>>,
     pub closed_auctions: &'a Rc<EventLink<usize, (Auction, Bid)>>,
     pub closed_auctions_flex: &'a Rc<EventLink<usize, (Auction, Bid)>>,
+    /// Commands migrating key bins between workers, see `crate::migration`.
+    pub control: &'a Rc<EventLink<usize, Control>>,
+    /// Checkpoints of the worker, which operators with checkpointed state register with.
+    pub checkpoints: &'a Checkpoints,
+    /// Sizes of the state of the worker's operators.
+    pub state_sizes: &'a StateSizes,
+    /// Partitioning of the worker's keyed exchanges.
+    pub partitioner: &'a Partitioner,
 }
 
 impl<'a> NexmarkInput<'a> {
@@ -62,6 +115,10 @@ impl<'a> NexmarkInput<'a> {
         Some(self.people.clone()).replay_into(scope)
     }
 
+    pub fn control<S: Scope<Timestamp = usize>>(&self, scope: &mut S) -> Stream<S, Control> {
+        Some(self.control.clone()).replay_into(scope)
+    }
+
     pub fn closed_auctions<S: Scope<Timestamp = usize>>(
         &self,
         scope: &mut S,
@@ -73,11 +130,14 @@ impl<'a> NexmarkInput<'a> {
 #[derive(Copy, Clone)]
 pub struct NexmarkTimer {
     pub time_dilation: usize,
+    /// Wall-clock instant processing time is measured from, taken before the worker builds its
+    /// dataflows. The harness starts its own clock once they are built.
+    pub start: Instant,
 }
 
 impl NexmarkTimer {
     #[inline(always)]
-    fn to_nexmark_time(self, x: usize) -> Date {
+    pub(crate) fn to_nexmark_time(self, x: usize) -> Date {
         debug_assert!(
             x.checked_mul(self.time_dilation).is_some(),
             "multiplication failed: {} * {}",
@@ -88,7 +148,13 @@ impl NexmarkTimer {
     }
 
     #[inline(always)]
-    fn from_nexmark_time(self, x: Date) -> usize {
+    pub(crate) fn from_nexmark_time(self, x: Date) -> usize {
         *x / self.time_dilation
     }
+
+    /// Current wall-clock processing time, in the same domain as event times.
+    #[inline(always)]
+    pub fn processing_time(self) -> Date {
+        self.to_nexmark_time(self.start.elapsed().to_nanos() as usize)
+    }
 }
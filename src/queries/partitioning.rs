@@ -0,0 +1,271 @@
+//! Partitioning of the keyed exchanges of the queries.
+//!
+//! Each keyed exchange extracts a key from its records, and the worker the key is sent to is
+//! chosen by the `Partitioning` strategy of the run, the same for all exchanges so that the
+//! inputs of a join meet. The hot keys of an operator's exchanges are spread over the workers on
+//! top of the strategy, see `HotKeys`. Exchanges can count the records they send to each worker,
+//! to tune the strategy against the skew of the input.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use timely::dataflow::channels::pact::Exchange;
+
+/// How keys are assigned to workers.
+#[derive(Clone, Debug)]
+pub enum Partitioning {
+    /// Scrambles keys with a hash before assigning them to workers round-robin.
+    Hash,
+    /// Assigns ranges of consecutive keys of the given width to workers round-robin. Ranges of
+    /// width 1 send key `k` to worker `k % peers`.
+    Range(u64),
+    /// Hashes keys into the given number of bins, and assigns the bins to workers round-robin.
+    Bins(usize),
+    /// Spreads the known hot keys over the workers round-robin, in the order of their rank, and
+    /// assigns the other keys by the inner strategy.
+    Skewed {
+        /// Stores hot key -> rank
+        hot: HashMap<u64, usize>,
+        others: Box<Partitioning>,
+    },
+}
+
+impl Default for Partitioning {
+    fn default() -> Self {
+        Partitioning::Range(1)
+    }
+}
+
+impl Partitioning {
+    /// Parses `hash`, `range:<width>` or `bins:<count>`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.splitn(2, ':');
+        let kind = parts.next().unwrap_or("");
+        let argument = parts.next();
+        let number = |name: &str| {
+            argument
+                .ok_or_else(|| format!("partitioning `{}` needs a {}", kind, name))?
+                .parse::<u64>()
+                .map_err(|_| format!("couldn't parse {} of partitioning `{}`", name, spec))
+                .and_then(|n| {
+                    if n > 0 {
+                        Ok(n)
+                    } else {
+                        Err(format!(
+                            "{} of partitioning `{}` must be positive",
+                            name, spec
+                        ))
+                    }
+                })
+        };
+        match kind {
+            "hash" => Ok(Partitioning::Hash),
+            "range" => Ok(Partitioning::Range(number("width")?)),
+            "bins" => Ok(Partitioning::Bins(number("bin count")? as usize)),
+            _ => Err(format!("unknown partitioning `{}`", spec)),
+        }
+    }
+
+    /// Spreads the `hot` keys, in decreasing order of load, over the workers, and assigns the
+    /// other keys like `self`.
+    pub fn with_hot_keys(self, hot: Vec<u64>) -> Self {
+        if hot.is_empty() {
+            return self;
+        }
+        let mut ranks = HashMap::new();
+        for key in hot.into_iter() {
+            let rank = ranks.len();
+            ranks.entry(key).or_insert(rank);
+        }
+        Partitioning::Skewed {
+            hot: ranks,
+            others: Box::new(self),
+        }
+    }
+
+    /// The worker of `peers` workers that `key` is sent to.
+    pub fn worker(&self, key: u64, peers: usize) -> usize {
+        match self {
+            Partitioning::Hash => (scramble(key) % peers as u64) as usize,
+            Partitioning::Range(width) => ((key / width) % peers as u64) as usize,
+            Partitioning::Bins(bins) => {
+                let bin = scramble(key) % *bins as u64;
+                (bin % peers as u64) as usize
+            }
+            Partitioning::Skewed { hot, others } => match hot.get(&key) {
+                Some(rank) => rank % peers,
+                None => others.worker(key, peers),
+            },
+        }
+    }
+}
+
+/// The hot keys of the exchanges of operators, which are spread over the workers on top of the
+/// strategy with `Partitioning::with_hot_keys`.
+///
+/// Hot keys are given per operator, and apply to all exchanges named after it, such as
+/// `Q20 Join/bids` and `Q20 Join/auctions` for `Q20 Join`, so that the inputs of a join still
+/// meet. The exchanges of other operators keep the plain strategy.
+#[derive(Clone, Debug, Default)]
+pub struct HotKeys(HashMap<String, Vec<u64>>);
+
+impl HotKeys {
+    /// Parses `<operator>=<key>,<key>,...`, adding the hot keys of the exchanges of `operator`,
+    /// hottest first.
+    pub fn add(&mut self, spec: &str) -> Result<(), String> {
+        let mut parts = spec.rsplitn(2, '=');
+        let keys = parts.next().unwrap_or("");
+        let operator = parts
+            .next()
+            .filter(|operator| !operator.is_empty())
+            .ok_or_else(|| {
+                format!(
+                    "hot keys `{}` need an operator, as `<operator>=<keys>`",
+                    spec
+                )
+            })?;
+        let keys = keys
+            .split(',')
+            .map(|key| {
+                key.trim()
+                    .parse::<u64>()
+                    .map_err(|_| format!("couldn't parse hot key `{}` of `{}`", key, spec))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.0
+            .entry(operator.to_string())
+            .or_insert_with(Vec::new)
+            .extend(keys);
+        Ok(())
+    }
+
+    /// The hot keys of the exchange `name`, which are those of the operator it is named after.
+    fn of(&self, name: &str) -> Vec<u64> {
+        let operator = name.split('/').next().unwrap_or(name);
+        self.0.get(operator).cloned().unwrap_or_default()
+    }
+}
+
+fn scramble(key: u64) -> u64 {
+    // Take the high bits of a multiplicative hash, which depend on all bits of the key.
+    key.wrapping_mul(0x9E37_79B9_7F4A_7C15).rotate_left(32)
+}
+
+/// The partitioning of the keyed exchanges of a worker.
+#[derive(Clone)]
+pub struct Partitioner {
+    strategy: Partitioning,
+    hot_keys: HotKeys,
+    peers: usize,
+    counts: RecordCounts,
+}
+
+impl Partitioner {
+    pub fn new(
+        strategy: Partitioning,
+        hot_keys: HotKeys,
+        peers: usize,
+        counts: RecordCounts,
+    ) -> Self {
+        Partitioner {
+            strategy,
+            hot_keys,
+            peers,
+            counts,
+        }
+    }
+
+    /// The worker that the exchange `name` sends `key` to.
+    pub fn worker(&self, name: &str, key: u64) -> usize {
+        self.strategy_of(name).worker(key, self.peers)
+    }
+
+    /// An exchange of records by `key`, counting the records sent to each worker under `name`.
+    pub fn exchange<D, K>(&self, name: &str, key: K) -> Exchange<D, impl Fn(&D) -> u64>
+    where
+        K: Fn(&D) -> u64 + 'static,
+    {
+        let strategy = self.strategy_of(name);
+        let peers = self.peers;
+        self.exchange_to(name, move |record| strategy.worker(key(record), peers))
+    }
+
+    /// An exchange of records to the worker chosen by `worker` instead of the strategy, such as
+    /// by the routing table of a migration, counting the records sent to each worker under `name`.
+    pub fn exchange_to<D, W>(&self, name: &str, worker: W) -> Exchange<D, impl Fn(&D) -> u64>
+    where
+        W: Fn(&D) -> usize + 'static,
+    {
+        let counts = self.counts.register(name, self.peers);
+        Exchange::new(move |record: &D| {
+            let worker = worker(record);
+            if let Some(ref counts) = counts {
+                counts[worker].fetch_add(1, Ordering::Relaxed);
+            }
+            worker as u64
+        })
+    }
+
+    /// The strategy of the exchange `name`, spreading the hot keys of its operator.
+    fn strategy_of(&self, name: &str) -> Partitioning {
+        self.strategy.clone().with_hot_keys(self.hot_keys.of(name))
+    }
+}
+
+/// The records sent to each worker by the keyed exchanges of a process.
+#[derive(Clone)]
+pub struct RecordCounts(Option<Arc<Mutex<Vec<(String, Arc<Vec<AtomicUsize>>)>>>>);
+
+impl RecordCounts {
+    pub fn new() -> Self {
+        RecordCounts(Some(Arc::new(Mutex::new(Vec::new()))))
+    }
+
+    /// Record counts that are not tracked.
+    pub fn disabled() -> Self {
+        RecordCounts(None)
+    }
+
+    /// Registers the counts of an exchange. The counts of exchanges registered under the same
+    /// name, by the same operator on different workers, are reported as one.
+    fn register(&self, name: &str, peers: usize) -> Option<Arc<Vec<AtomicUsize>>> {
+        self.0.as_ref().map(|registry| {
+            let counts = Arc::new((0..peers).map(|_| AtomicUsize::new(0)).collect::<Vec<_>>());
+            registry
+                .lock()
+                .unwrap()
+                .push((name.to_string(), counts.clone()));
+            counts
+        })
+    }
+
+    /// The counts as `exchange_records` lines of exchange name, worker and the number of records
+    /// sent to the worker by the workers of this process.
+    pub fn report(&self) -> Vec<String> {
+        let registry = match self.0 {
+            Some(ref registry) => registry.lock().unwrap(),
+            None => return Vec::new(),
+        };
+        let mut totals: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (name, counts) in registry.iter() {
+            let total = totals
+                .entry(name.clone())
+                .or_insert_with(|| vec![0; counts.len()]);
+            for (total, count) in total.iter_mut().zip(counts.iter()) {
+                *total += count.load(Ordering::Relaxed);
+            }
+        }
+        totals
+            .into_iter()
+            .flat_map(|(name, counts)| {
+                counts
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(worker, records)| {
+                        format!("exchange_records\t{}\t{}\t{}", name, worker, records)
+                    })
+            })
+            .collect()
+    }
+}
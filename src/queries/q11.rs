@@ -0,0 +1,33 @@
+use timely::dataflow::operators::Map;
+use timely::dataflow::{Scope, Stream};
+
+use crate::event::Date;
+use crate::windows::{self, Backing, Count};
+
+use crate::queries::{NexmarkInput, NexmarkTimer};
+
+/// Counts the bids of each bidder's sessions, keeping the window state as selected by `backing`.
+pub fn q11<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    nt: NexmarkTimer,
+    scope: &mut S,
+    session_gap_ns: usize,
+    backing: Backing,
+) -> Stream<S, (usize, usize)> {
+    let bids = input.bids(scope).map(|b| (b.bidder, b.date_time, ()));
+
+    // Count the bids of each bidder's sessions.
+    windows::session(
+        &bids,
+        input
+            .partitioner
+            .exchange("Q11 Sessions", |b: &(usize, Date, ())| b.0 as u64),
+        "Q11 Sessions",
+        input.checkpoints,
+        input.state_sizes,
+        nt,
+        session_gap_ns,
+        backing,
+    )
+    .map(|(bidder, _end, count): (usize, Date, Count)| (bidder, count.0))
+}
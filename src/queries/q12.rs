@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+
+use crate::event::{Bid, Date};
+
+use crate::queries::{NexmarkInput, NexmarkTimer};
+
+pub fn q12<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    nt: NexmarkTimer,
+    scope: &mut S,
+    window_size_ns: usize,
+) -> Stream<S, (usize, usize)> {
+    // Stores window -> bidder -> count
+    let mut windows: HashMap<_, HashMap<usize, usize>> = HashMap::new();
+
+    input.bids(scope).unary_notify(
+        input
+            .partitioner
+            .exchange("Q12 Processing Time Windows", |b: &Bid| b.bidder as u64),
+        "Q12 Processing Time Windows",
+        None,
+        move |input, output, notificator, _state_handle| {
+            input.for_each(|time, data| {
+                // Windows are assigned on arrival, ignoring the bids' event time.
+                let window =
+                    Date::new(((*nt.processing_time() / window_size_ns) + 1) * window_size_ns);
+                let window = std::cmp::max(nt.from_nexmark_time(window), *time.time());
+                notificator.notify_at(time.delayed(&window));
+                let counts = windows.entry(window).or_insert_with(HashMap::new);
+                for bid in data.iter() {
+                    *counts.entry(bid.bidder).or_insert(0) += 1;
+                }
+            });
+
+            notificator.for_each(|cap, _, _| {
+                if let Some(counts) = windows.remove(cap.time()) {
+                    output.session(&cap).give_iterator(counts.into_iter());
+                }
+            });
+        },
+    )
+}
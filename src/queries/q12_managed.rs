@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+use timely::state::primitives::ManagedMap;
+
+use crate::event::{Bid, Date};
+
+use crate::queries::{NexmarkInput, NexmarkTimer};
+use faster_rs::FasterRmw;
+
+#[derive(Deserialize, Serialize)]
+struct Counts(HashMap<usize, usize>);
+
+impl FasterRmw for Counts {
+    fn rmw(&self, modification: Self) -> Self {
+        let mut counts = self.0.clone();
+        for (bidder, count) in modification.0.into_iter() {
+            *counts.entry(bidder).or_insert(0) += count;
+        }
+        Counts(counts)
+    }
+}
+
+pub fn q12_managed<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    nt: NexmarkTimer,
+    scope: &mut S,
+    window_size_ns: usize,
+) -> Stream<S, (usize, usize)> {
+    input.bids(scope).unary_notify(
+        input.partitioner.exchange("Q12 Processing Time Windows", |b: &Bid| b.bidder as u64),
+        "Q12 Processing Time Windows",
+        None,
+        move |input, output, notificator, state_handle| {
+            // Stores window -> bidder -> count
+            let mut windows: Box<ManagedMap<usize, Counts>> = state_handle.get_managed_map("windows");
+
+            input.for_each(|time, data| {
+                // Windows are assigned on arrival, ignoring the bids' event time.
+                let window =
+                    Date::new(((*nt.processing_time() / window_size_ns) + 1) * window_size_ns);
+                let window = std::cmp::max(nt.from_nexmark_time(window), *time.time());
+                notificator.notify_at(time.delayed(&window));
+                // Count the batch, and merge it into the window's counts with a single write.
+                let mut counts = Counts(HashMap::new());
+                for bid in data.iter() {
+                    *counts.0.entry(bid.bidder).or_insert(0) += 1;
+                }
+                windows.rmw(window, counts);
+            });
+
+            notificator.for_each(|cap, _, _| {
+                if let Some(counts) = windows.remove(cap.time()) {
+                    output.session(&cap).give_iterator(counts.0.into_iter());
+                }
+            });
+        },
+    )
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+use timely::worker::AsWorker;
+
+use crate::event::Bid;
+
+use crate::queries::{NexmarkInput, NexmarkTimer, Partitioner};
+
+/// A bounded table of `key -> value` rows that bids are joined against in Q13.
+///
+/// Bids are looked up under `auction % len()`, so keys are expected to lie in `0..len()`.
+#[derive(Clone, Default)]
+pub struct SideInput {
+    rows: Vec<(usize, String)>,
+}
+
+impl SideInput {
+    /// Generates `size` rows mapping each key to its decimal representation.
+    pub fn generate(size: usize) -> Self {
+        SideInput {
+            rows: (0..size).map(|key| (key, format!("{}", key))).collect(),
+        }
+    }
+
+    /// Reads rows from a file containing one `key,value` pair per line.
+    pub fn from_file(path: &str) -> Result<Self> {
+        let mut rows = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(2, ',');
+            let key = fields
+                .next()
+                .and_then(|key| key.trim().parse::<usize>().ok())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Invalid side input key."))?;
+            let value = fields.next().unwrap_or("").to_string();
+            rows.push((key, value));
+        }
+        Ok(SideInput { rows })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Returns the rows the exchange `name` on `key` routes to the given worker.
+    pub(crate) fn worker_rows<'a>(
+        &'a self,
+        index: usize,
+        partitioner: &'a Partitioner,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a (usize, String)> + 'a {
+        self.rows
+            .iter()
+            .filter(move |&&(key, _)| partitioner.worker(name, key as u64) == index)
+    }
+}
+
+pub fn q13<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    _nt: NexmarkTimer,
+    scope: &mut S,
+    side_input: &SideInput,
+) -> Stream<S, (Bid, String)> {
+    assert!(!side_input.is_empty(), "Q13 requires a non-empty side input");
+    let size = side_input.len();
+    let table: HashMap<usize, String> = side_input
+        .worker_rows(scope.index(), input.partitioner, "Q13 Side Input Join")
+        .cloned()
+        .collect();
+
+    input.bids(scope).unary(
+        input.partitioner.exchange("Q13 Side Input Join", move |b: &Bid| (b.auction % size) as u64),
+        "Q13 Side Input Join",
+        move |_capability, _info, _state_handle| {
+            move |input, output| {
+                input.for_each(|time, data| {
+                    let mut session = output.session(&time);
+                    for bid in data.iter().cloned() {
+                        if let Some(value) = table.get(&(bid.auction % size)) {
+                            session.give((bid, value.clone()));
+                        }
+                    }
+                });
+            }
+        },
+    )
+}
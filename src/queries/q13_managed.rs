@@ -0,0 +1,59 @@
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+use timely::state::primitives::ManagedMap;
+use timely::worker::AsWorker;
+
+use crate::event::Bid;
+use crate::primitives::Batch;
+
+use crate::queries::{NexmarkInput, NexmarkTimer, SideInput};
+use faster_rs::FasterRmw;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SideInputValue(String);
+
+impl FasterRmw for SideInputValue {
+    fn rmw(&self, _modification: Self) -> Self {
+        panic!("RMW on SideInputValue not allowed!");
+    }
+}
+
+pub fn q13_managed<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    _nt: NexmarkTimer,
+    scope: &mut S,
+    side_input: &SideInput,
+) -> Stream<S, (Bid, String)> {
+    assert!(!side_input.is_empty(), "Q13 requires a non-empty side input");
+    let size = side_input.len();
+    let rows: Vec<(usize, String)> = side_input
+        .worker_rows(scope.index(), input.partitioner, "Q13 Side Input Join")
+        .cloned()
+        .collect();
+
+    input.bids(scope).unary(
+        input.partitioner.exchange("Q13 Side Input Join", move |b: &Bid| (b.auction % size) as u64),
+        "Q13 Side Input Join",
+        move |_capability, _info, state_handle| {
+            // Stores key -> value, only read after loading
+            let mut table: Box<ManagedMap<usize, SideInputValue>> =
+                state_handle.get_managed_map("side_input");
+            for (key, value) in rows {
+                table.insert(key, SideInputValue(value));
+            }
+
+            move |input, output| {
+                input.for_each(|time, data| {
+                    let mut session = output.session(&time);
+                    let keys = data.iter().map(|bid| bid.auction % size);
+                    let mut values = Batch::read(&mut table, keys);
+                    for bid in data.iter().cloned() {
+                        if let Some(value) = values.get(&(bid.auction % size)) {
+                            session.give((bid, value.0.clone()));
+                        }
+                    }
+                });
+            }
+        },
+    )
+}
@@ -0,0 +1,63 @@
+use std::ops::Range;
+
+use timely::dataflow::operators::{Filter, Map};
+use timely::dataflow::{Scope, Stream};
+
+use crate::event::Date;
+
+use {crate::queries::NexmarkInput, crate::queries::NexmarkTimer};
+
+const HOUR_NS: usize = 60 * 60 * 1_000_000_000;
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum BidTimeType {
+    DayTime,
+    NightTime,
+    OtherTime,
+}
+
+/// The hour of the day of `date_time`.
+fn hour_of(date_time: Date) -> usize {
+    (*date_time / HOUR_NS) % 24
+}
+
+impl BidTimeType {
+    fn of(date_time: Date) -> Self {
+        let hour = hour_of(date_time);
+        if hour >= 8 && hour <= 18 {
+            BidTimeType::DayTime
+        } else if hour <= 6 || hour >= 20 {
+            BidTimeType::NightTime
+        } else {
+            BidTimeType::OtherTime
+        }
+    }
+}
+
+/// Filters bids by converted price and by the hour of the day they were made in, which must be
+/// in `hours`, and classifies them by the time of day.
+pub fn q14<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    _nt: NexmarkTimer,
+    scope: &mut S,
+    hours: Range<usize>,
+) -> Stream<S, (usize, usize, usize, BidTimeType, Date, String, usize)> {
+    input
+        .bids(scope)
+        .map_in_place(|b| b.price = (b.price * 908) / 1000)
+        .filter(move |b| {
+            b.price > 1_000_000 && b.price < 50_000_000 && hours.contains(&hour_of(b.date_time))
+        })
+        .map(|b| {
+            let c_counts = b.extra.chars().filter(|c| *c == 'c').count();
+            (
+                b.auction,
+                b.bidder,
+                b.price,
+                BidTimeType::of(b.date_time),
+                b.date_time,
+                b.extra,
+                c_counts,
+            )
+        })
+}
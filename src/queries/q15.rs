@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+
+use crate::event::Date;
+
+use crate::queries::q15_q16_common::{DailyAccumulator, DailyPartial, DailyStatistics};
+use crate::queries::{NexmarkInput, NexmarkTimer};
+
+/// Computes the daily statistics of the bids in two stages: each worker accumulates the days of
+/// the bids it read, and once a day ends its partial accumulators are exchanged by day and merged,
+/// so that the bids of a day are not all counted by a single worker.
+pub fn q15<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    nt: NexmarkTimer,
+    scope: &mut S,
+    day_ns: usize,
+    approximate: bool,
+) -> Stream<S, DailyStatistics> {
+    // Stores end of day -> statistics of the bids read by this worker
+    let mut partials: HashMap<usize, DailyAccumulator> = HashMap::new();
+    // Stores end of day -> statistics merged from all workers
+    let mut days: HashMap<usize, DailyAccumulator> = HashMap::new();
+    let mut buffer = Vec::new();
+
+    input
+        .bids(scope)
+        .unary_notify(
+            Pipeline,
+            "Q15 Pre-aggregate",
+            None,
+            move |input, output, notificator, _state_handle| {
+                input.for_each(|time, data| {
+                    for bid in data.iter() {
+                        let end_of_day = nt
+                            .from_nexmark_time(Date::new(((*bid.date_time / day_ns) + 1) * day_ns));
+                        partials
+                            .entry(end_of_day)
+                            .or_insert_with(|| {
+                                notificator.notify_at(time.delayed(&end_of_day));
+                                DailyAccumulator::new(approximate)
+                            })
+                            .add(bid);
+                    }
+                });
+
+                notificator.for_each(|cap, _, _| {
+                    if let Some(partial) = partials.remove(cap.time()) {
+                        let day = *nt.to_nexmark_time(*cap.time()) / day_ns - 1;
+                        output.session(&cap).give((day, partial.partial()));
+                    }
+                });
+            },
+        )
+        .unary_notify(
+            input
+                .partitioner
+                .exchange("Q15 Daily Statistics", |p: &(usize, DailyPartial)| {
+                    p.0 as u64
+                }),
+            "Q15 Daily Statistics",
+            None,
+            move |input, output, notificator, _state_handle| {
+                input.for_each(|time, data| {
+                    data.swap(&mut buffer);
+                    for (_, partial) in buffer.drain(..) {
+                        days.entry(*time.time())
+                            .or_insert_with(|| {
+                                notificator.notify_at(time.retain());
+                                DailyAccumulator::new(approximate)
+                            })
+                            .merge(partial);
+                    }
+                });
+
+                notificator.for_each(|cap, _, _| {
+                    if let Some(accumulator) = days.remove(cap.time()) {
+                        let day = *nt.to_nexmark_time(*cap.time()) / day_ns - 1;
+                        output.session(&cap).give(accumulator.statistics(day));
+                    }
+                });
+            },
+        )
+}
@@ -0,0 +1,99 @@
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+use timely::state::primitives::ManagedMap;
+
+use crate::event::{Bid, Date};
+use crate::primitives::Batch;
+
+use crate::queries::q15_q16_common::{DailyAccumulator, DailyPartial, DailyStatistics};
+use crate::queries::{NexmarkInput, NexmarkTimer};
+
+/// Computes the daily statistics of the bids in two stages like `q15`, keeping the accumulators
+/// of both stages in managed maps.
+pub fn q15_managed<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    nt: NexmarkTimer,
+    scope: &mut S,
+    day_ns: usize,
+    approximate: bool,
+) -> Stream<S, DailyStatistics> {
+    let mut buffer = Vec::new();
+
+    input
+        .bids(scope)
+        .unary_notify(
+            Pipeline,
+            "Q15 Pre-aggregate",
+            None,
+            move |input, output, notificator, state_handle| {
+                // Stores end of day -> statistics of the bids read by this worker, including the
+                // distinct bidder and auction sets
+                let mut partials: Box<ManagedMap<usize, DailyAccumulator>> =
+                    state_handle.get_managed_map("partials");
+
+                input.for_each(|time, data| {
+                    let end_of = move |bid: &Bid| {
+                        nt.from_nexmark_time(Date::new(((*bid.date_time / day_ns) + 1) * day_ns))
+                    };
+                    // Read each day touched by the batch once, and write it back once.
+                    let mut touched = Batch::read(&mut partials, data.iter().map(end_of));
+                    for bid in data.iter() {
+                        let end_of_day = end_of(bid);
+                        touched.update_or_insert_with(
+                            end_of_day,
+                            || {
+                                notificator.notify_at(time.delayed(&end_of_day));
+                                DailyAccumulator::new(approximate)
+                            },
+                            |accumulator| accumulator.add(bid),
+                        );
+                    }
+                });
+
+                notificator.for_each(|cap, _, _| {
+                    if let Some(partial) = partials.remove(cap.time()) {
+                        let day = *nt.to_nexmark_time(*cap.time()) / day_ns - 1;
+                        output.session(&cap).give((day, partial.partial()));
+                    }
+                });
+            },
+        )
+        .unary_notify(
+            input
+                .partitioner
+                .exchange("Q15 Daily Statistics", |p: &(usize, DailyPartial)| {
+                    p.0 as u64
+                }),
+            "Q15 Daily Statistics",
+            None,
+            move |input, output, notificator, state_handle| {
+                // Stores end of day -> statistics merged from all workers
+                let mut days: Box<ManagedMap<usize, DailyAccumulator>> =
+                    state_handle.get_managed_map("days");
+
+                input.for_each(|time, data| {
+                    data.swap(&mut buffer);
+                    let end_of_day = *time.time();
+                    let mut touched = Batch::read(&mut days, Some(end_of_day));
+                    for (_, partial) in buffer.drain(..) {
+                        touched.update_or_insert_with(
+                            end_of_day,
+                            || {
+                                notificator.notify_at(time.retain());
+                                DailyAccumulator::new(approximate)
+                            },
+                            |accumulator| accumulator.merge(partial),
+                        );
+                    }
+                });
+
+                notificator.for_each(|cap, _, _| {
+                    if let Some(accumulator) = days.remove(cap.time()) {
+                        let day = *nt.to_nexmark_time(*cap.time()) / day_ns - 1;
+                        output.session(&cap).give(accumulator.statistics(day));
+                    }
+                });
+            },
+        )
+}
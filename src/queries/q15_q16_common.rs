@@ -0,0 +1,300 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+use faster_rs::FasterRmw;
+
+use crate::event::{Bid, Date};
+
+/// Number of buckets the statistics are split into: all bids, then one per price rank.
+pub const RANKS: usize = 4;
+
+/// Hashes a channel name to route its bids.
+pub(crate) fn hash_channel(channel: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    channel.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Returns the price rank of a bid, as used by Flink's daily statistics queries.
+pub(crate) fn price_rank(price: usize) -> usize {
+    if price < 10_000 {
+        1
+    } else if price < 1_000_000 {
+        2
+    } else {
+        3
+    }
+}
+
+const HLL_PRECISION: u32 = 12;
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+
+/// A HyperLogLog sketch with 2^12 one byte registers, about 1.6% standard error.
+#[derive(Clone, Serialize, Deserialize, Abomonation)]
+pub(crate) struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        HyperLogLog {
+            registers: vec![0; HLL_REGISTERS],
+        }
+    }
+
+    pub fn insert<T: Hash>(&mut self, value: &T) {
+        // DefaultHasher::new uses fixed keys, so sketches agree across workers.
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let register = (hash >> (64 - HLL_PRECISION)) as usize;
+        let rank = ((hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1))).leading_zeros() + 1;
+        if self.registers[register] < rank as u8 {
+            self.registers[register] = rank as u8;
+        }
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        for (register, other) in self.registers.iter_mut().zip(other.registers.iter()) {
+            if *register < *other {
+                *register = *other;
+            }
+        }
+    }
+
+    pub fn estimate(&self) -> usize {
+        let m = HLL_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&r| 2.0_f64.powi(-(r as i32)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            // Small range correction: fall back to linear counting.
+            (m * (m / zeros as f64).ln()).round() as usize
+        } else {
+            estimate.round() as usize
+        }
+    }
+}
+
+/// Counts distinct values, either exactly or approximately.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) enum DistinctCount {
+    Exact(HashSet<usize>),
+    Approximate(HyperLogLog),
+}
+
+impl DistinctCount {
+    pub fn new(approximate: bool) -> Self {
+        if approximate {
+            DistinctCount::Approximate(HyperLogLog::new())
+        } else {
+            DistinctCount::Exact(HashSet::new())
+        }
+    }
+
+    pub fn insert(&mut self, value: usize) {
+        match self {
+            DistinctCount::Exact(set) => {
+                set.insert(value);
+            }
+            DistinctCount::Approximate(sketch) => sketch.insert(&value),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        match self {
+            DistinctCount::Exact(set) => set.len(),
+            DistinctCount::Approximate(sketch) => sketch.estimate(),
+        }
+    }
+
+    /// Adds the values counted by `other`, which counts the same way.
+    pub fn merge(&mut self, other: &DistinctValues) {
+        match (self, other) {
+            (DistinctCount::Exact(set), DistinctValues::Exact(values)) => {
+                set.extend(values.iter().cloned())
+            }
+            (DistinctCount::Approximate(sketch), DistinctValues::Approximate(other)) => {
+                sketch.merge(other)
+            }
+            _ => panic!("can't merge exact and approximate distinct counts"),
+        }
+    }
+
+    /// The values counted, to send them to another worker.
+    pub fn values(&self) -> DistinctValues {
+        match self {
+            DistinctCount::Exact(set) => DistinctValues::Exact(set.iter().cloned().collect()),
+            DistinctCount::Approximate(sketch) => DistinctValues::Approximate(sketch.clone()),
+        }
+    }
+}
+
+/// The values of a `DistinctCount`, in a form that can be exchanged between workers.
+#[derive(Clone, Serialize, Deserialize, Abomonation)]
+pub(crate) enum DistinctValues {
+    Exact(Vec<usize>),
+    Approximate(HyperLogLog),
+}
+
+/// Per-day bid statistics, each split into all bids followed by the three price ranks.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DailyStatistics {
+    pub day: usize,
+    pub bids: [usize; RANKS],
+    pub bidders: [usize; RANKS],
+    pub auctions: [usize; RANKS],
+}
+
+/// Accumulates the bids, distinct bidders and distinct auctions of one day.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct DailyAccumulator {
+    latest: Date,
+    bids: [usize; RANKS],
+    bidders: Vec<DistinctCount>,
+    auctions: Vec<DistinctCount>,
+}
+
+impl FasterRmw for DailyAccumulator {
+    fn rmw(&self, _modification: Self) -> Self {
+        panic!("RMW on DailyAccumulator not allowed!");
+    }
+}
+
+/// The accumulator of a day's bids on one worker, to merge with those of the other workers.
+#[derive(Clone, Serialize, Deserialize, Abomonation)]
+pub(crate) struct DailyPartial {
+    latest: Date,
+    bids: [usize; RANKS],
+    bidders: Vec<DistinctValues>,
+    auctions: Vec<DistinctValues>,
+}
+
+impl DailyAccumulator {
+    pub fn new(approximate: bool) -> Self {
+        DailyAccumulator {
+            latest: Date::new(0),
+            bids: [0; RANKS],
+            bidders: (0..RANKS).map(|_| DistinctCount::new(approximate)).collect(),
+            auctions: (0..RANKS).map(|_| DistinctCount::new(approximate)).collect(),
+        }
+    }
+
+    pub fn add(&mut self, bid: &Bid) {
+        self.latest = std::cmp::max(self.latest, bid.date_time);
+        for &rank in [0, price_rank(bid.price)].iter() {
+            self.bids[rank] += 1;
+            self.bidders[rank].insert(bid.bidder);
+            self.auctions[rank].insert(bid.auction);
+        }
+    }
+
+    /// Adds the bids accumulated by `partial`, which counts distinct values the same way.
+    pub fn merge(&mut self, partial: DailyPartial) {
+        self.latest = std::cmp::max(self.latest, partial.latest);
+        for rank in 0..RANKS {
+            self.bids[rank] += partial.bids[rank];
+            self.bidders[rank].merge(&partial.bidders[rank]);
+            self.auctions[rank].merge(&partial.auctions[rank]);
+        }
+    }
+
+    /// The bids accumulated, to merge them with those of other workers.
+    pub fn partial(&self) -> DailyPartial {
+        DailyPartial {
+            latest: self.latest,
+            bids: self.bids,
+            bidders: self.bidders.iter().map(DistinctCount::values).collect(),
+            auctions: self.auctions.iter().map(DistinctCount::values).collect(),
+        }
+    }
+
+    /// Returns the time of the latest bid added.
+    pub fn latest(&self) -> Date {
+        self.latest
+    }
+
+    pub fn statistics(&self, day: usize) -> DailyStatistics {
+        let mut statistics = DailyStatistics {
+            day,
+            bids: self.bids,
+            bidders: [0; RANKS],
+            auctions: [0; RANKS],
+        };
+        for rank in 0..RANKS {
+            statistics.bidders[rank] = self.bidders[rank].count();
+            statistics.auctions[rank] = self.auctions[rank].count();
+        }
+        statistics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DailyAccumulator, HyperLogLog};
+    use crate::event::{Bid, Date};
+
+    fn error(estimate: usize, count: usize) -> f64 {
+        (estimate as f64 - count as f64).abs() / count as f64
+    }
+
+    #[test]
+    fn hyperloglog_estimates_distinct_values() {
+        for &count in [100usize, 10_000, 100_000].iter() {
+            let mut sketch = HyperLogLog::new();
+            for value in 0..count {
+                sketch.insert(&value);
+                sketch.insert(&value);
+            }
+            let estimate = sketch.estimate();
+            assert!(error(estimate, count) < 0.05, "{} for {}", estimate, count);
+        }
+    }
+
+    #[test]
+    fn merged_hyperloglogs_estimate_the_union() {
+        let (mut first, mut second) = (HyperLogLog::new(), HyperLogLog::new());
+        for value in 0..6_000usize {
+            first.insert(&value);
+        }
+        for value in 4_000..10_000usize {
+            second.insert(&value);
+        }
+        first.merge(&second);
+        let estimate = first.estimate();
+        assert!(error(estimate, 10_000) < 0.05, "{} for 10000", estimate);
+    }
+
+    #[test]
+    fn merged_partials_count_like_one_accumulator() {
+        let bid = |auction, bidder, price| Bid {
+            auction,
+            bidder,
+            price,
+            channel: String::new(),
+            url: String::new(),
+            date_time: Date::new(auction),
+            extra: String::new(),
+        };
+        let bids = vec![bid(1, 1, 10), bid(2, 1, 20_000), bid(1, 2, 10), bid(3, 3, 2_000_000)];
+        let mut whole = DailyAccumulator::new(false);
+        let (mut first, mut second) = (DailyAccumulator::new(false), DailyAccumulator::new(false));
+        for (index, bid) in bids.iter().enumerate() {
+            whole.add(bid);
+            if index % 2 == 0 {
+                first.add(bid);
+            } else {
+                second.add(bid);
+            }
+        }
+        first.merge(second.partial());
+        assert_eq!(first.statistics(0), whole.statistics(0));
+        assert_eq!(first.latest(), whole.latest());
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+
+use crate::event::{Bid, Date};
+
+use crate::queries::q15_q16_common::{hash_channel, DailyAccumulator, DailyStatistics};
+use crate::queries::{NexmarkInput, NexmarkTimer};
+
+const MINUTE_NS: usize = 60 * 1_000_000_000;
+
+pub fn q16<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    nt: NexmarkTimer,
+    scope: &mut S,
+    day_ns: usize,
+    approximate: bool,
+) -> Stream<S, (String, usize, DailyStatistics)> {
+    // Stores end of day -> channel -> statistics
+    let mut days: HashMap<usize, HashMap<String, DailyAccumulator>> = HashMap::new();
+
+    input.bids(scope).unary_notify(
+        input
+            .partitioner
+            .exchange("Q16 Channel Statistics", |b: &Bid| hash_channel(&b.channel)),
+        "Q16 Channel Statistics",
+        None,
+        move |input, output, notificator, _state_handle| {
+            input.for_each(|time, data| {
+                for bid in data.iter() {
+                    let end_of_day =
+                        nt.from_nexmark_time(Date::new(((*bid.date_time / day_ns) + 1) * day_ns));
+                    days.entry(end_of_day)
+                        .or_insert_with(|| {
+                            notificator.notify_at(time.delayed(&end_of_day));
+                            HashMap::new()
+                        })
+                        .entry(bid.channel.clone())
+                        .or_insert_with(|| DailyAccumulator::new(approximate))
+                        .add(bid);
+                }
+            });
+
+            notificator.for_each(|cap, _, _| {
+                if let Some(channels) = days.remove(cap.time()) {
+                    let day = *nt.to_nexmark_time(*cap.time()) / day_ns - 1;
+                    let mut session = output.session(&cap);
+                    for (channel, accumulator) in channels.into_iter() {
+                        // Minute of the day of the channel's latest bid
+                        let minute = (*accumulator.latest() % day_ns) / MINUTE_NS;
+                        session.give((channel, minute, accumulator.statistics(day)));
+                    }
+                }
+            });
+        },
+    )
+}
@@ -0,0 +1,69 @@
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+use timely::state::primitives::ManagedMap;
+
+use crate::event::{Bid, Date};
+use crate::primitives::Batch;
+
+use crate::queries::q15_q16_common::{hash_channel, DailyAccumulator, DailyStatistics};
+use crate::queries::{NexmarkInput, NexmarkTimer};
+
+const MINUTE_NS: usize = 60 * 1_000_000_000;
+
+pub fn q16_managed<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    nt: NexmarkTimer,
+    scope: &mut S,
+    day_ns: usize,
+    approximate: bool,
+) -> Stream<S, (String, usize, DailyStatistics)> {
+    input.bids(scope).unary_notify(
+        input
+            .partitioner
+            .exchange("Q16 Channel Statistics", |b: &Bid| hash_channel(&b.channel)),
+        "Q16 Channel Statistics",
+        None,
+        move |input, output, notificator, state_handle| {
+            // Stores (channel, end of day) -> statistics
+            let mut statistics: Box<ManagedMap<(String, usize), DailyAccumulator>> =
+                state_handle.get_managed_map("statistics");
+            // Stores end of day -> channels with statistics that day
+            let mut channels: Box<ManagedMap<usize, Vec<String>>> =
+                state_handle.get_managed_map("channels");
+
+            input.for_each(|time, data| {
+                let key = move |bid: &Bid| {
+                    let end_of_day =
+                        nt.from_nexmark_time(Date::new(((*bid.date_time / day_ns) + 1) * day_ns));
+                    (bid.channel.clone(), end_of_day)
+                };
+                // Read each (channel, day) touched by the batch once, and write it back once.
+                let mut touched = Batch::read(&mut statistics, data.iter().map(key));
+                for bid in data.iter() {
+                    let (channel, end_of_day) = key(bid);
+                    touched.update_or_insert_with(
+                        (channel, end_of_day),
+                        || {
+                            notificator.notify_at(time.delayed(&end_of_day));
+                            channels.rmw(end_of_day, vec![bid.channel.clone()]);
+                            DailyAccumulator::new(approximate)
+                        },
+                        |accumulator| accumulator.add(bid),
+                    );
+                }
+            });
+
+            notificator.for_each(|cap, _, _| {
+                let day = *nt.to_nexmark_time(*cap.time()) / day_ns - 1;
+                let mut session = output.session(&cap);
+                for channel in channels.remove(cap.time()).unwrap_or(Vec::new()) {
+                    if let Some(accumulator) = statistics.remove(&(channel.clone(), *cap.time())) {
+                        // Minute of the day of the channel's latest bid
+                        let minute = (*accumulator.latest() % day_ns) / MINUTE_NS;
+                        session.give((channel, minute, accumulator.statistics(day)));
+                    }
+                }
+            });
+        },
+    )
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+
+use crate::event::{Bid, Date};
+
+use crate::queries::q15_q16_common::price_rank;
+use crate::queries::{NexmarkInput, NexmarkTimer};
+use faster_rs::FasterRmw;
+
+/// Bid count and price statistics of one auction on one day.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PriceStatistics {
+    /// All bids, then the bids in each of the three price ranks.
+    pub bids: [usize; 4],
+    pub min_price: usize,
+    pub max_price: usize,
+    pub sum_price: usize,
+}
+
+impl PriceStatistics {
+    pub fn from_price(price: usize) -> Self {
+        let mut bids = [1, 0, 0, 0];
+        bids[price_rank(price)] += 1;
+        PriceStatistics {
+            bids,
+            min_price: price,
+            max_price: price,
+            sum_price: price,
+        }
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        for (bids, other) in self.bids.iter_mut().zip(other.bids.iter()) {
+            *bids += other;
+        }
+        self.min_price = std::cmp::min(self.min_price, other.min_price);
+        self.max_price = std::cmp::max(self.max_price, other.max_price);
+        self.sum_price += other.sum_price;
+    }
+
+    pub fn avg_price(&self) -> usize {
+        self.sum_price / self.bids[0]
+    }
+}
+
+impl FasterRmw for PriceStatistics {
+    fn rmw(&self, modification: Self) -> Self {
+        let mut merged = self.clone();
+        merged.merge(&modification);
+        merged
+    }
+}
+
+pub fn q17<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    nt: NexmarkTimer,
+    scope: &mut S,
+    day_ns: usize,
+) -> Stream<S, (usize, usize, PriceStatistics)> {
+    // Stores end of day -> auction -> statistics
+    let mut days: HashMap<usize, HashMap<usize, PriceStatistics>> = HashMap::new();
+
+    input.bids(scope).unary_notify(
+        input
+            .partitioner
+            .exchange("Q17 Auction Statistics", |b: &Bid| b.auction as u64),
+        "Q17 Auction Statistics",
+        None,
+        move |input, output, notificator, _state_handle| {
+            input.for_each(|time, data| {
+                for bid in data.iter() {
+                    let end_of_day =
+                        nt.from_nexmark_time(Date::new(((*bid.date_time / day_ns) + 1) * day_ns));
+                    let statistics = PriceStatistics::from_price(bid.price);
+                    days.entry(end_of_day)
+                        .or_insert_with(|| {
+                            notificator.notify_at(time.delayed(&end_of_day));
+                            HashMap::new()
+                        })
+                        .entry(bid.auction)
+                        .and_modify(|existing| existing.merge(&statistics))
+                        .or_insert(statistics);
+                }
+            });
+
+            notificator.for_each(|cap, _, _| {
+                if let Some(auctions) = days.remove(cap.time()) {
+                    let day = *nt.to_nexmark_time(*cap.time()) / day_ns - 1;
+                    output.session(&cap).give_iterator(
+                        auctions
+                            .into_iter()
+                            .map(|(auction, statistics)| (auction, day, statistics)),
+                    );
+                }
+            });
+        },
+    )
+}
@@ -0,0 +1,71 @@
+use std::collections::{HashMap, HashSet};
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+use timely::state::primitives::ManagedMap;
+
+use crate::event::{Bid, Date};
+use crate::primitives::ManagedMultiMap;
+
+use crate::queries::{NexmarkInput, NexmarkTimer, PriceStatistics};
+
+pub fn q17_managed<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    nt: NexmarkTimer,
+    scope: &mut S,
+    day_ns: usize,
+) -> Stream<S, (usize, usize, PriceStatistics)> {
+    input.bids(scope).unary_notify(
+        input
+            .partitioner
+            .exchange("Q17 Auction Statistics", |b: &Bid| b.auction as u64),
+        "Q17 Auction Statistics",
+        None,
+        move |input, output, notificator, state_handle| {
+            // Stores (auction, end of day) -> statistics, updated through RMW
+            let mut statistics: Box<ManagedMap<(usize, usize), PriceStatistics>> =
+                state_handle.get_managed_map("statistics");
+            // Stores end of day -> auctions with statistics that day, appended once per batch
+            // without looking up the statistics, so an auction can be listed more than once
+            let mut auctions: ManagedMultiMap<usize, usize> =
+                ManagedMultiMap::new(state_handle.get_managed_map("auctions"));
+
+            input.for_each(|time, data| {
+                // Combine the batch's bids per (auction, day) into a single delta.
+                let mut deltas: HashMap<(usize, usize), PriceStatistics> = HashMap::new();
+                for bid in data.iter() {
+                    let end_of_day =
+                        nt.from_nexmark_time(Date::new(((*bid.date_time / day_ns) + 1) * day_ns));
+                    let delta = PriceStatistics::from_price(bid.price);
+                    deltas
+                        .entry((bid.auction, end_of_day))
+                        .and_modify(|existing| existing.merge(&delta))
+                        .or_insert(delta);
+                }
+                let mut days: HashMap<usize, Vec<usize>> = HashMap::new();
+                for ((auction, end_of_day), delta) in deltas.into_iter() {
+                    days.entry(end_of_day).or_default().push(auction);
+                    statistics.rmw((auction, end_of_day), delta);
+                }
+                for (end_of_day, day_auctions) in days.into_iter() {
+                    notificator.notify_at(time.delayed(&end_of_day));
+                    auctions.extend(end_of_day, day_auctions);
+                }
+            });
+
+            notificator.for_each(|cap, _, _| {
+                let day = *nt.to_nexmark_time(*cap.time()) / day_ns - 1;
+                let mut session = output.session(&cap);
+                let day_auctions: HashSet<usize> = auctions
+                    .remove(cap.time())
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect();
+                for auction in day_auctions.into_iter() {
+                    if let Some(auction_statistics) = statistics.remove(&(auction, *cap.time())) {
+                        session.give((auction, day, auction_statistics));
+                    }
+                }
+            });
+        },
+    )
+}
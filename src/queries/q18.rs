@@ -0,0 +1,42 @@
+use std::collections::HashMap;
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+
+use crate::event::Bid;
+
+use crate::queries::{NexmarkInput, NexmarkTimer};
+
+pub fn q18<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    _nt: NexmarkTimer,
+    scope: &mut S,
+) -> Stream<S, Bid> {
+    input.bids(scope).unary(
+        input.partitioner.exchange("Q18 Deduplicate", |b: &Bid| {
+            (b.bidder as u64)
+                .wrapping_mul(31)
+                .wrapping_add(b.auction as u64)
+        }),
+        "Q18 Deduplicate",
+        |_capability, _info, _state_handle| {
+            // Stores (bidder, auction) -> latest bid
+            let mut latest: HashMap<(usize, usize), Bid> = HashMap::new();
+
+            move |input, output| {
+                input.for_each(|time, data| {
+                    let mut session = output.session(&time);
+                    for bid in data.iter().cloned() {
+                        let key = (bid.bidder, bid.auction);
+                        let newer = latest
+                            .get(&key)
+                            .map_or(true, |existing| existing.date_time <= bid.date_time);
+                        if newer {
+                            session.give(bid.clone());
+                            latest.insert(key, bid);
+                        }
+                    }
+                });
+            }
+        },
+    )
+}
@@ -0,0 +1,57 @@
+use faster_rs::FasterRmw;
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+use timely::state::primitives::ManagedMap;
+
+use crate::event::Bid;
+
+use crate::queries::{NexmarkInput, NexmarkTimer};
+
+/// The latest bid of a bidder on an auction, which merges by keeping the newer bid.
+#[derive(Clone, Serialize, Deserialize, Abomonation)]
+struct LatestBid(Bid);
+
+impl FasterRmw for LatestBid {
+    fn rmw(&self, modification: Self) -> Self {
+        if (modification.0).date_time >= (self.0).date_time {
+            modification
+        } else {
+            self.clone()
+        }
+    }
+}
+
+pub fn q18_managed<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    _nt: NexmarkTimer,
+    scope: &mut S,
+) -> Stream<S, Bid> {
+    input.bids(scope).unary(
+        input.partitioner.exchange("Q18 Deduplicate", |b: &Bid| {
+            (b.bidder as u64)
+                .wrapping_mul(31)
+                .wrapping_add(b.auction as u64)
+        }),
+        "Q18 Deduplicate",
+        |_capability, _info, state_handle| {
+            // Stores (bidder, auction) -> latest bid
+            let mut latest: Box<ManagedMap<(usize, usize), LatestBid>> =
+                state_handle.get_managed_map("latest");
+
+            move |input, output| {
+                input.for_each(|time, data| {
+                    let mut session = output.session(&time);
+                    for bid in data.iter().cloned() {
+                        // Merge the bid into the latest one, which keeps it if it is newer.
+                        let key = (bid.bidder, bid.auction);
+                        latest.rmw(key, LatestBid(bid.clone()));
+                        let merged = latest.get(&key).expect("merged bid must exist");
+                        if merged.0 == bid {
+                            session.give(bid);
+                        }
+                    }
+                });
+            }
+        },
+    )
+}
@@ -0,0 +1,121 @@
+use std::collections::{HashMap, HashSet};
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+
+use crate::event::{Bid, Date};
+
+use crate::queries::{NexmarkInput, NexmarkTimer};
+use faster_rs::FasterRmw;
+
+const TOP_N: usize = 10;
+
+/// How Q19 reports changes to an auction's top bids.
+#[derive(Clone, Copy, Debug)]
+pub enum TopNEmission {
+    /// Emit the new top bids of an auction whenever they change.
+    Updates,
+    /// Every given number of nanoseconds of event time, emit the top bids of each auction that
+    /// changed during that period.
+    Snapshots(usize),
+}
+
+/// The highest bids of an auction, ordered by decreasing price and bounded to `TOP_N`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct TopBids(Vec<Bid>);
+
+impl TopBids {
+    /// Inserts a bid, returning whether the top bids changed.
+    pub(crate) fn insert(&mut self, bid: Bid) -> bool {
+        let position = self
+            .0
+            .iter()
+            .position(|b| b.price < bid.price)
+            .unwrap_or(self.0.len());
+        if position >= TOP_N {
+            return false;
+        }
+        self.0.insert(position, bid);
+        self.0.truncate(TOP_N);
+        true
+    }
+
+    pub(crate) fn bids(&self) -> Vec<Bid> {
+        self.0.clone()
+    }
+}
+
+impl FasterRmw for TopBids {
+    fn rmw(&self, modification: Self) -> Self {
+        let mut merged = self.clone();
+        for bid in modification.0.into_iter() {
+            merged.insert(bid);
+        }
+        merged
+    }
+}
+
+pub fn q19<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    nt: NexmarkTimer,
+    scope: &mut S,
+    emission: TopNEmission,
+) -> Stream<S, (usize, Vec<Bid>)> {
+    // Stores auction -> top bids
+    let mut top: HashMap<usize, TopBids> = HashMap::new();
+    // Stores end of snapshot period -> auctions whose top bids changed
+    let mut changed: HashMap<usize, HashSet<usize>> = HashMap::new();
+
+    input.bids(scope).unary_notify(
+        input
+            .partitioner
+            .exchange("Q19 Top Bids", |b: &Bid| b.auction as u64),
+        "Q19 Top Bids",
+        None,
+        move |input, output, notificator, _state_handle| {
+            input.for_each(|time, data| {
+                let mut updated = HashSet::new();
+                for bid in data.iter().cloned() {
+                    let auction = bid.auction;
+                    let date_time = bid.date_time;
+                    if !top
+                        .entry(auction)
+                        .or_insert_with(TopBids::default)
+                        .insert(bid)
+                    {
+                        continue;
+                    }
+                    match emission {
+                        TopNEmission::Updates => {
+                            updated.insert(auction);
+                        }
+                        TopNEmission::Snapshots(period_ns) => {
+                            let end_of_period = nt.from_nexmark_time(Date::new(
+                                ((*date_time / period_ns) + 1) * period_ns,
+                            ));
+                            changed
+                                .entry(end_of_period)
+                                .or_insert_with(|| {
+                                    notificator.notify_at(time.delayed(&end_of_period));
+                                    HashSet::new()
+                                })
+                                .insert(auction);
+                        }
+                    }
+                }
+                let mut session = output.session(&time);
+                for auction in updated.into_iter() {
+                    session.give((auction, top[&auction].bids()));
+                }
+            });
+
+            notificator.for_each(|cap, _, _| {
+                if let Some(auctions) = changed.remove(cap.time()) {
+                    let mut session = output.session(&cap);
+                    for auction in auctions.into_iter() {
+                        session.give((auction, top[&auction].bids()));
+                    }
+                }
+            });
+        },
+    )
+}
@@ -0,0 +1,85 @@
+use std::collections::{HashMap, HashSet};
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+use timely::state::primitives::ManagedMap;
+
+use crate::event::{Bid, Date};
+
+use crate::queries::q19::{TopBids, TopNEmission};
+use crate::queries::{NexmarkInput, NexmarkTimer};
+
+pub fn q19_managed<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    nt: NexmarkTimer,
+    scope: &mut S,
+    emission: TopNEmission,
+) -> Stream<S, (usize, Vec<Bid>)> {
+    input.bids(scope).unary_notify(
+        input
+            .partitioner
+            .exchange("Q19 Top Bids", |b: &Bid| b.auction as u64),
+        "Q19 Top Bids",
+        None,
+        move |input, output, notificator, state_handle| {
+            // Stores auction -> top bids
+            let mut top: Box<ManagedMap<usize, TopBids>> = state_handle.get_managed_map("top");
+            // Stores end of snapshot period -> auctions whose top bids changed
+            let mut changed: Box<ManagedMap<usize, Vec<usize>>> =
+                state_handle.get_managed_map("changed");
+
+            input.for_each(|time, data| {
+                // Read each auction touched by the batch once, and write back the changed ones.
+                let mut touched: HashMap<usize, (TopBids, bool)> = HashMap::new();
+                let mut periods: HashMap<usize, HashSet<usize>> = HashMap::new();
+                for bid in data.iter().cloned() {
+                    let auction = bid.auction;
+                    let date_time = bid.date_time;
+                    let entry = touched.entry(auction).or_insert_with(|| {
+                        let bids = top
+                            .get(&auction)
+                            .map_or(TopBids::default(), |t| (*t).clone());
+                        (bids, false)
+                    });
+                    if !entry.0.insert(bid) {
+                        continue;
+                    }
+                    entry.1 = true;
+                    if let TopNEmission::Snapshots(period_ns) = emission {
+                        let end_of_period = nt.from_nexmark_time(Date::new(
+                            ((*date_time / period_ns) + 1) * period_ns,
+                        ));
+                        periods.entry(end_of_period).or_default().insert(auction);
+                    }
+                }
+
+                let mut session = output.session(&time);
+                for (auction, (bids, updated)) in touched.into_iter() {
+                    if updated {
+                        if let TopNEmission::Updates = emission {
+                            session.give((auction, bids.bids()));
+                        }
+                        top.insert(auction, bids);
+                    }
+                }
+                for (end_of_period, auctions) in periods.into_iter() {
+                    if changed.get(&end_of_period).is_none() {
+                        notificator.notify_at(time.delayed(&end_of_period));
+                    }
+                    changed.rmw(end_of_period, auctions.into_iter().collect());
+                }
+            });
+
+            notificator.for_each(|cap, _, _| {
+                let mut auctions = changed.remove(cap.time()).unwrap_or(Vec::new());
+                auctions.sort();
+                auctions.dedup();
+                let mut session = output.session(&cap);
+                for auction in auctions.into_iter() {
+                    if let Some(bids) = top.get(&auction) {
+                        session.give((auction, bids.bids()));
+                    }
+                }
+            });
+        },
+    )
+}
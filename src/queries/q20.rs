@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+
+use crate::event::{Auction, Bid};
+
+use crate::queries::{NexmarkInput, NexmarkTimer};
+use faster_rs::FasterRmw;
+
+/// Join state of one auction id.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) enum JoinState {
+    /// Bids that arrived before their auction.
+    Pending(Vec<Bid>),
+    /// An auction in the category, joined with each of its bids until it expires.
+    Matched(Auction),
+    /// An auction in another category, whose bids are dropped until it expires.
+    Excluded(usize),
+}
+
+impl JoinState {
+    /// The timely time at which the state can be dropped, if an auction has arrived.
+    pub(crate) fn expiry(&self, nt: NexmarkTimer) -> Option<usize> {
+        match *self {
+            JoinState::Pending(_) => None,
+            JoinState::Matched(ref auction) => Some(nt.from_nexmark_time(auction.expires)),
+            JoinState::Excluded(expiry) => Some(expiry),
+        }
+    }
+}
+
+impl FasterRmw for JoinState {
+    fn rmw(&self, _modification: Self) -> Self {
+        panic!("RMW on JoinState not allowed!");
+    }
+}
+
+pub fn q20<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    nt: NexmarkTimer,
+    scope: &mut S,
+    category: usize,
+) -> Stream<S, (Auction, Bid)> {
+    let bids = input.bids(scope);
+    let auctions = input.auctions(scope);
+
+    // Stores auction id -> join state
+    let mut state: HashMap<usize, JoinState> = HashMap::new();
+    // Stores time -> auction ids to clean up
+    let mut cleanups: HashMap<usize, Vec<usize>> = HashMap::new();
+
+    bids.binary_notify(
+        &auctions,
+        input
+            .partitioner
+            .exchange("Q20 Join/bids", |b: &Bid| b.auction as u64),
+        input
+            .partitioner
+            .exchange("Q20 Join/auctions", |a: &Auction| a.id as u64),
+        "Q20 Join",
+        None,
+        move |input1, input2, output, notificator, _state_handle| {
+            // Join each bid with its auction, or buffer it until the auction arrives.
+            input1.for_each(|time, data| {
+                let mut session = output.session(&time);
+                for bid in data.iter().cloned() {
+                    match state.get_mut(&bid.auction) {
+                        Some(JoinState::Matched(auction)) => session.give((auction.clone(), bid)),
+                        Some(JoinState::Excluded(_)) => {}
+                        Some(JoinState::Pending(bids)) => bids.push(bid),
+                        None => {
+                            // Auctions precede their bids, so once this time completes without
+                            // the auction arriving, it never will.
+                            notificator.notify_at(time.retain());
+                            cleanups
+                                .entry(*time.time())
+                                .or_insert_with(Vec::new)
+                                .push(bid.auction);
+                            state.insert(bid.auction, JoinState::Pending(vec![bid]));
+                        }
+                    }
+                }
+            });
+
+            // Record each auction, joining it with any buffered bids.
+            input2.for_each(|time, data| {
+                let mut session = output.session(&time);
+                for auction in data.iter().cloned() {
+                    let expiry = std::cmp::max(nt.from_nexmark_time(auction.expires), *time.time());
+                    notificator.notify_at(time.delayed(&expiry));
+                    cleanups
+                        .entry(expiry)
+                        .or_insert_with(Vec::new)
+                        .push(auction.id);
+                    let pending = match state.remove(&auction.id) {
+                        Some(JoinState::Pending(bids)) => bids,
+                        _ => Vec::new(),
+                    };
+                    if auction.category == category {
+                        for bid in pending.into_iter() {
+                            session.give((auction.clone(), bid));
+                        }
+                        state.insert(auction.id, JoinState::Matched(auction));
+                    } else {
+                        state.insert(auction.id, JoinState::Excluded(expiry));
+                    }
+                }
+            });
+
+            notificator.for_each(|cap, _, _| {
+                for id in cleanups.remove(cap.time()).unwrap_or(Vec::new()) {
+                    let expired = state
+                        .get(&id)
+                        .map_or(false, |s| s.expiry(nt).map_or(true, |e| e <= *cap.time()));
+                    if expired {
+                        state.remove(&id);
+                    }
+                }
+            });
+        },
+    )
+}
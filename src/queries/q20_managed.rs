@@ -0,0 +1,102 @@
+use timely::dataflow::operators::Operator;
+use timely::dataflow::{Scope, Stream};
+use timely::state::primitives::ManagedMap;
+
+use crate::event::{Auction, Bid};
+use crate::primitives::Batch;
+
+use crate::queries::q20::JoinState;
+use crate::queries::{NexmarkInput, NexmarkTimer};
+
+pub fn q20_managed<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    nt: NexmarkTimer,
+    scope: &mut S,
+    category: usize,
+) -> Stream<S, (Auction, Bid)> {
+    let bids = input.bids(scope);
+    let auctions = input.auctions(scope);
+
+    bids.binary_notify(
+        &auctions,
+        input
+            .partitioner
+            .exchange("Q20 Join/bids", |b: &Bid| b.auction as u64),
+        input
+            .partitioner
+            .exchange("Q20 Join/auctions", |a: &Auction| a.id as u64),
+        "Q20 Join",
+        None,
+        move |input1, input2, output, notificator, state_handle| {
+            // Stores auction id -> join state
+            let mut state: Box<ManagedMap<usize, JoinState>> =
+                state_handle.get_managed_map("state");
+            // Stores time -> auction ids to clean up
+            let mut cleanups: Box<ManagedMap<usize, Vec<usize>>> =
+                state_handle.get_managed_map("cleanups");
+
+            // Join each bid with its auction, or buffer it until the auction arrives.
+            input1.for_each(|time, data| {
+                let mut session = output.session(&time);
+                let mut state = Batch::read(&mut state, data.iter().map(|bid| bid.auction));
+                for bid in data.iter().cloned() {
+                    let auction_id = bid.auction;
+                    match state.get(&auction_id) {
+                        Some(JoinState::Matched(auction)) => {
+                            session.give((auction.clone(), bid));
+                        }
+                        Some(JoinState::Excluded(_)) => {}
+                        Some(JoinState::Pending(_)) => {
+                            state.update(&auction_id, |pending| {
+                                if let JoinState::Pending(bids) = pending {
+                                    bids.push(bid);
+                                }
+                            });
+                        }
+                        None => {
+                            // Auctions precede their bids, so once this time completes without
+                            // the auction arriving, it never will.
+                            notificator.notify_at(time.retain());
+                            cleanups.rmw(*time.time(), vec![auction_id]);
+                            state.insert(auction_id, JoinState::Pending(vec![bid]));
+                        }
+                    }
+                }
+            });
+
+            // Record each auction, joining it with any buffered bids.
+            input2.for_each(|time, data| {
+                let mut session = output.session(&time);
+                let mut state = Batch::read(&mut state, data.iter().map(|auction| auction.id));
+                for auction in data.iter().cloned() {
+                    let expiry = std::cmp::max(nt.from_nexmark_time(auction.expires), *time.time());
+                    notificator.notify_at(time.delayed(&expiry));
+                    cleanups.rmw(expiry, vec![auction.id]);
+                    let pending = match state.remove(&auction.id) {
+                        Some(JoinState::Pending(bids)) => bids,
+                        _ => Vec::new(),
+                    };
+                    if auction.category == category {
+                        for bid in pending.into_iter() {
+                            session.give((auction.clone(), bid));
+                        }
+                        state.insert(auction.id, JoinState::Matched(auction));
+                    } else {
+                        state.insert(auction.id, JoinState::Excluded(expiry));
+                    }
+                }
+            });
+
+            notificator.for_each(|cap, _, _| {
+                for id in cleanups.remove(cap.time()).unwrap_or(Vec::new()) {
+                    let expired = state
+                        .get(&id)
+                        .map_or(false, |s| s.expiry(nt).map_or(true, |e| e <= *cap.time()));
+                    if expired {
+                        state.remove(&id);
+                    }
+                }
+            });
+        },
+    )
+}
@@ -0,0 +1,38 @@
+use timely::dataflow::operators::Map;
+use timely::dataflow::{Scope, Stream};
+
+use {crate::queries::NexmarkInput, crate::queries::NexmarkTimer};
+
+/// Returns the channel id, known for the hot channels and otherwise parsed from the url.
+fn channel_id(channel: &str, url: &str) -> Option<String> {
+    match channel.to_lowercase().as_str() {
+        "apple" => Some("0".to_string()),
+        "google" => Some("1".to_string()),
+        "facebook" => Some("2".to_string()),
+        "baidu" => Some("3".to_string()),
+        // Equivalent to extracting group 2 of `(&|^)channel_id=([^&]*)`, where an empty id counts
+        // as none, like Flink's `NULLIF`.
+        _ => url
+            .split('&')
+            .filter_map(|parameter| {
+                if parameter.starts_with("channel_id=") {
+                    Some(parameter["channel_id=".len()..].to_string())
+                } else {
+                    None
+                }
+            })
+            .next()
+            .filter(|channel_id| !channel_id.is_empty()),
+    }
+}
+
+pub fn q21<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    _nt: NexmarkTimer,
+    scope: &mut S,
+) -> Stream<S, (usize, usize, usize, String, String)> {
+    input.bids(scope).flat_map(|b| {
+        channel_id(&b.channel, &b.url)
+            .map(|channel_id| (b.auction, b.bidder, b.price, b.channel, channel_id))
+    })
+}
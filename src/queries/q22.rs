@@ -0,0 +1,23 @@
+use timely::dataflow::operators::Map;
+use timely::dataflow::{Scope, Stream};
+
+use {crate::queries::NexmarkInput, crate::queries::NexmarkTimer};
+
+pub fn q22<S: Scope<Timestamp = usize>>(
+    input: &NexmarkInput,
+    _nt: NexmarkTimer,
+    scope: &mut S,
+) -> Stream<S, (usize, usize, usize, String, String, String, String)> {
+    input.bids(scope).map(|b| {
+        // Urls look like `https://www.nexmark.com/<dir1>/<dir2>/<dir3>/item.htm?query=1`.
+        let (dir1, dir2, dir3) = {
+            let mut directories = b.url.split('/').skip(3).map(String::from);
+            (
+                directories.next().unwrap_or_default(),
+                directories.next().unwrap_or_default(),
+                directories.next().unwrap_or_default(),
+            )
+        };
+        (b.auction, b.bidder, b.price, b.channel, dir1, dir2, dir3)
+    })
+}
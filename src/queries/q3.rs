@@ -1,16 +1,26 @@
-use std::collections::HashMap;
-use timely::dataflow::channels::pact::Exchange;
+use std::collections::{BTreeMap, HashMap};
 use timely::dataflow::operators::{Filter, Operator};
 use timely::dataflow::{Scope, Stream};
 
-use crate::event::{Auction, Person};
+use crate::event::{Auction, Date, Person};
 
 use crate::queries::{NexmarkInput, NexmarkTimer};
+use crate::state_sizes::bytes_of;
 
+/// The time at which state with event time `date` expires after `ttl_ns`, rounded up to the
+/// millisecond so that expiring state is grouped into few timers.
+pub(crate) fn ttl_expiry(nt: NexmarkTimer, date: Date, ttl_ns: usize) -> usize {
+    let expiry = nt.from_nexmark_time(date + Date::new(ttl_ns));
+    (expiry / 1_000_000 + 1) * 1_000_000
+}
+
+/// Joins people with the auctions they sell. With `ttl_ns`, people and auctions are evicted once
+/// the frontier passes their event time plus `ttl_ns`, and no longer join.
 pub fn q3<S: Scope<Timestamp = usize>>(
     input: &NexmarkInput,
-    _nt: NexmarkTimer,
+    nt: NexmarkTimer,
     scope: &mut S,
+    ttl_ns: Option<usize>,
 ) -> Stream<S, (String, String, String, usize)> {
     let auctions = input.auctions(scope).filter(|a| a.category == 10);
 
@@ -21,14 +31,25 @@ pub fn q3<S: Scope<Timestamp = usize>>(
     let mut auctions_buffer = vec![];
     let mut people_buffer = vec![];
 
-    auctions.binary(
+    let state1_size = input.state_sizes.register("Q3 Join/state1");
+    let state2_size = input.state_sizes.register("Q3 Join/state2");
+
+    auctions.binary_frontier(
         &people,
-        Exchange::new(|a: &Auction| a.seller as u64 / 100),
-        Exchange::new(|p: &Person| p.id as u64 / 100),
+        input
+            .partitioner
+            .exchange("Q3 Join/auctions", |a: &Auction| a.seller as u64 / 100),
+        input
+            .partitioner
+            .exchange("Q3 Join/people", |p: &Person| p.id as u64 / 100),
         "Q3 Join",
         |_capability, _info, _state_handle| {
             let mut state1 = HashMap::new();
             let mut state2 = HashMap::<usize, Person>::new();
+            // Stores expiry time -> sellers with auctions expiring then
+            let mut expirations1: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+            // Stores expiry time -> people expiring then
+            let mut expirations2: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
 
             move |input1, input2, output| {
                 // Process each input auction.
@@ -44,6 +65,12 @@ pub fn q3<S: Scope<Timestamp = usize>>(
                                 auction.id,
                             ));
                         }
+                        if let Some(ttl_ns) = ttl_ns {
+                            expirations1
+                                .entry(ttl_expiry(nt, auction.date_time, ttl_ns))
+                                .or_insert_with(Vec::new)
+                                .push(auction.seller);
+                        }
                         state1
                             .entry(auction.seller)
                             .or_insert(Vec::new())
@@ -66,9 +93,65 @@ pub fn q3<S: Scope<Timestamp = usize>>(
                                 ));
                             }
                         }
+                        if let Some(ttl_ns) = ttl_ns {
+                            expirations2
+                                .entry(ttl_expiry(nt, person.date_time, ttl_ns))
+                                .or_insert_with(Vec::new)
+                                .push(person.id);
+                        }
                         state2.insert(person.id, person);
                     }
                 });
+
+                // Evict the state that expired before the frontier.
+                if let Some(ttl_ns) = ttl_ns {
+                    let complete1 = input1
+                        .frontier
+                        .frontier()
+                        .get(0)
+                        .cloned()
+                        .unwrap_or(usize::max_value());
+                    let complete2 = input2
+                        .frontier
+                        .frontier()
+                        .get(0)
+                        .cloned()
+                        .unwrap_or(usize::max_value());
+                    let complete = std::cmp::min(complete1, complete2);
+
+                    let pending = expirations1.split_off(&complete);
+                    for (time, sellers) in std::mem::replace(&mut expirations1, pending) {
+                        for seller in sellers {
+                            let empty = match state1.get_mut(&seller) {
+                                Some(auctions) => {
+                                    auctions.retain(|auction: &Auction| {
+                                        ttl_expiry(nt, auction.date_time, ttl_ns) > time
+                                    });
+                                    auctions.is_empty()
+                                }
+                                None => false,
+                            };
+                            if empty {
+                                state1.remove(&seller);
+                            }
+                        }
+                    }
+                    let pending = expirations2.split_off(&complete);
+                    for (_, people) in std::mem::replace(&mut expirations2, pending) {
+                        for person in people {
+                            state2.remove(&person);
+                        }
+                    }
+                }
+
+                if state1_size.is_due() {
+                    let bytes = state1.iter().map(|(k, v)| bytes_of(k) + bytes_of(v)).sum();
+                    state1_size.set(state1.len(), bytes);
+                }
+                if state2_size.is_due() {
+                    let bytes = state2.iter().map(|(k, v)| bytes_of(k) + bytes_of(v)).sum();
+                    state2_size.set(state2.len(), bytes);
+                }
             }
         },
     )
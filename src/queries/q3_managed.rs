@@ -1,76 +1,395 @@
-use timely::dataflow::channels::pact::Exchange;
-use timely::dataflow::operators::{Filter, Operator};
+use timely::dataflow::operators::{Capability, Filter, Operator};
 use timely::dataflow::{Scope, Stream};
+use timely::scheduling::Scheduler;
 use timely::state::primitives::ManagedMap;
+use timely::worker::AsWorker;
 
 use crate::event::{Auction, Person};
+use crate::migration::{bin_of, BinIndex, Held, Migrator, Routed, Routing, BINS};
+use crate::primitives::{Batch, ManagedMultiMap, ManagedTimers};
+use crate::state_sizes::StateSize;
 
+use crate::queries::q3::ttl_expiry;
 use crate::queries::{NexmarkInput, NexmarkTimer};
 
+type Output = (String, String, String, usize);
+
+/// Timestamps per slot of the expiry timers, one millisecond.
+const TIMER_GRANULARITY: usize = 1_000_000;
+
+/// The key sellers are routed by, which groups a hundred sellers.
+fn seller_key(seller: usize) -> u64 {
+    seller as u64 / 100
+}
+
+/// The state of the sellers of a bin, as it moves between workers.
+#[derive(Clone, Serialize, Deserialize, Abomonation)]
+struct SellersBin {
+    /// Stores (seller, auctions)
+    auctions: Vec<(usize, Vec<Auction>)>,
+    people: Vec<Person>,
+}
+
+/// Snapshot of the join and its routing, for checkpoints.
+#[derive(Serialize, Deserialize)]
+struct JoinSnapshot {
+    routing: Routing,
+    bins: Vec<SellersBin>,
+}
+
+struct Join {
+    nt: NexmarkTimer,
+    ttl_ns: Option<usize>,
+    state1: ManagedMultiMap<usize, Auction>,
+    state2: Box<ManagedMap<usize, Person>>,
+    // Timers at the expiry times of auctions, for their sellers
+    expirations1: ManagedTimers<usize>,
+    // Timers at the expiry times of people
+    expirations2: ManagedTimers<usize>,
+    /// Stores bin -> sellers with auctions or a person in the state, to extract the bin
+    sellers: BinIndex,
+    state1_size: StateSize,
+    state2_size: StateSize,
+}
+
+impl Join {
+    /// Joins `auctions` with the people in the state and records them.
+    fn auctions(&mut self, auctions: Vec<Auction>, joined: &mut Vec<Output>) {
+        let mut sellers: Vec<usize> = auctions.iter().map(|auction| auction.seller).collect();
+        let mut people = Batch::read(&mut self.state2, sellers.clone());
+        for auction in auctions.into_iter() {
+            if let Some(person) = people.get(&auction.seller) {
+                joined.push((
+                    person.name.clone(),
+                    person.city.clone(),
+                    person.state.clone(),
+                    auction.id,
+                ));
+            }
+            let seller = auction.seller;
+            if let Some(ttl_ns) = self.ttl_ns {
+                let expiry = ttl_expiry(self.nt, auction.date_time, ttl_ns);
+                self.expirations1.register(expiry, seller);
+            }
+            let new_seller = self.state1_size.is_enabled() && self.state1.get(&seller).is_none();
+            self.state1_size.added(new_seller as usize, &auction);
+            self.state1.append(seller, auction);
+        }
+        drop(people);
+        sellers.sort();
+        sellers.dedup();
+        for seller in sellers.into_iter() {
+            self.index(seller);
+        }
+    }
+
+    /// Joins `people` with the auctions in the state and records them.
+    fn people(&mut self, people: Vec<Person>, joined: &mut Vec<Output>) {
+        let mut sellers = self.state1.batch(people.iter().map(|person| person.id));
+        let mut state2 = Batch::read(&mut self.state2, Vec::new());
+        let mut indexed = Vec::new();
+        for person in people.into_iter() {
+            if let Some(auctions) = sellers.get(&person.id) {
+                for auction in auctions.iter() {
+                    joined.push((
+                        person.name.clone(),
+                        person.city.clone(),
+                        person.state.clone(),
+                        auction.id,
+                    ));
+                }
+            }
+            self.state2_size.added(1, &person);
+            if let Some(ttl_ns) = self.ttl_ns {
+                let expiry = ttl_expiry(self.nt, person.date_time, ttl_ns);
+                self.expirations2.register(expiry, person.id);
+            }
+            indexed.push(person.id);
+            state2.insert(person.id, person);
+        }
+        drop(state2);
+        drop(sellers);
+        for seller in indexed.into_iter() {
+            self.index(seller);
+        }
+    }
+
+    /// Evicts the state that expired before `complete`.
+    fn evict(&mut self, complete: usize) {
+        let ttl_ns = match self.ttl_ns {
+            Some(ttl_ns) => ttl_ns,
+            None => return,
+        };
+        let nt = self.nt;
+        for (time, seller) in self.expirations1.fire(complete) {
+            if let Some(auctions) = self.state1.remove(&seller) {
+                let (expired, auctions): (Vec<Auction>, Vec<Auction>) = auctions
+                    .into_iter()
+                    .partition(|auction| ttl_expiry(nt, auction.date_time, ttl_ns) <= time);
+                self.state1_size
+                    .removed(auctions.is_empty() as usize, &expired);
+                if auctions.is_empty() && self.state2.get(&seller).is_none() {
+                    self.unindex(seller);
+                }
+                self.state1.replace(seller, auctions);
+            }
+        }
+        for (_, person) in self.expirations2.fire(complete) {
+            if let Some(person) = self.state2.remove(&person) {
+                self.state2_size.removed(1, &person);
+                if self.state1.get(&person.id).is_none() {
+                    self.unindex(person.id);
+                }
+            }
+        }
+    }
+
+    /// Adds `seller` to the sellers of its bin.
+    fn index(&mut self, seller: usize) {
+        let (state1, state2) = (&mut self.state1, &mut self.state2);
+        self.sellers
+            .insert(bin_of(seller_key(seller)), seller, |seller| {
+                state1.get(&seller).is_some() || state2.get(&seller).is_some()
+            });
+    }
+
+    /// Records that `seller` left the state of its bin.
+    fn unindex(&mut self, seller: usize) {
+        let (state1, state2) = (&mut self.state1, &mut self.state2);
+        self.sellers.remove(bin_of(seller_key(seller)), |seller| {
+            state1.get(&seller).is_some() || state2.get(&seller).is_some()
+        });
+    }
+
+    /// Removes and returns the state of the sellers of `bin`. Their expirations stay behind and
+    /// find no state when they are due.
+    fn extract(&mut self, bin: usize) -> SellersBin {
+        let mut extracted = SellersBin {
+            auctions: Vec::new(),
+            people: Vec::new(),
+        };
+        for seller in self.sellers.take(bin) {
+            if let Some(auctions) = self.state1.remove(&seller) {
+                self.state1_size.removed(1, &auctions);
+                extracted.auctions.push((seller, auctions));
+            }
+            if let Some(person) = self.state2.remove(&seller) {
+                self.state2_size.removed(1, &person);
+                extracted.people.push(person);
+            }
+        }
+        extracted
+    }
+
+    /// The state of the sellers of `bin`, which stays in place, for checkpoints.
+    fn capture(&mut self, bin: usize) -> SellersBin {
+        let (state1, state2) = (&mut self.state1, &mut self.state2);
+        let sellers = self.sellers.keys(bin, |seller| {
+            state1.get(&seller).is_some() || state2.get(&seller).is_some()
+        });
+        let mut captured = SellersBin {
+            auctions: Vec::new(),
+            people: Vec::new(),
+        };
+        for seller in sellers.into_iter() {
+            if let Some(auctions) = self.state1.get(&seller) {
+                captured.auctions.push((seller, (*auctions).clone()));
+            }
+            if let Some(person) = self.state2.get(&seller) {
+                captured.people.push((*person).clone());
+            }
+        }
+        captured
+    }
+
+    /// Installs the state of the sellers of a bin extracted at another worker.
+    fn install(&mut self, bin: SellersBin) {
+        for (seller, auctions) in bin.auctions.into_iter() {
+            if let Some(ttl_ns) = self.ttl_ns {
+                for auction in auctions.iter() {
+                    let expiry = ttl_expiry(self.nt, auction.date_time, ttl_ns);
+                    self.expirations1.register(expiry, seller);
+                }
+            }
+            self.state1_size.added(1, &auctions);
+            self.state1.extend(seller, auctions);
+            self.index(seller);
+        }
+        for person in bin.people.into_iter() {
+            if let Some(ttl_ns) = self.ttl_ns {
+                let expiry = ttl_expiry(self.nt, person.date_time, ttl_ns);
+                self.expirations2.register(expiry, person.id);
+            }
+            self.state2_size.added(1, &person);
+            let seller = person.id;
+            self.state2.insert(seller, person);
+            self.index(seller);
+        }
+    }
+}
+
+/// Joins people with the auctions they sell, like `q3`, keeping the state in managed maps.
+///
+/// Sellers are routed in bins that the control stream can migrate to other workers, with their
+/// state, see `crate::migration`.
 pub fn q3_managed<S: Scope<Timestamp = usize>>(
     input: &NexmarkInput,
-    _nt: NexmarkTimer,
+    nt: NexmarkTimer,
     scope: &mut S,
-) -> Stream<S, (String, String, String, usize)> {
+    ttl_ns: Option<usize>,
+) -> Stream<S, Output> {
     let auctions = input.auctions(scope).filter(|a| a.category == 10);
 
     let people = input
         .people(scope)
         .filter(|p| p.state == "OR" || p.state == "ID" || p.state == "CA");
 
+    let control = input.control(scope);
+    let migrator: Migrator<SellersBin> = Migrator::new(scope.index(), scope.peers());
+    // The auctions' router is created first, so it sends the bins.
+    let auctions = migrator.route(&auctions, &control, "Q3 Route auctions", |a: &Auction| {
+        seller_key(a.seller)
+    });
+    let people = migrator.route(&people, &control, "Q3 Route people", |p: &Person| {
+        seller_key(p.id)
+    });
+
     let mut auctions_buffer = vec![];
     let mut people_buffer = vec![];
 
-    auctions.binary(
+    let checkpoints = input.checkpoints.clone();
+    let scope = scope.clone();
+
+    let state1_size = input.state_sizes.register("Q3 Join/state1");
+    let state2_size = input.state_sizes.register("Q3 Join/state2");
+
+    auctions.binary_frontier(
         &people,
-        Exchange::new(|a: &Auction| a.seller as u64 / 100),
-        Exchange::new(|p: &Person| p.id as u64 / 100),
+        input.partitioner.exchange_to(
+            "Q3 Join/auctions",
+            |a: &(usize, Routed<Auction, SellersBin>)| a.0,
+        ),
+        input.partitioner.exchange_to(
+            "Q3 Join/people",
+            |p: &(usize, Routed<Person, SellersBin>)| p.0,
+        ),
         "Q3 Join",
-        |_capability, _info, state_handle| {
-            let mut state1: Box<ManagedMap<usize, Vec<Auction>>> =
-                state_handle.get_managed_map("state1");
-            let mut state2: Box<ManagedMap<usize, Person>> = state_handle.get_managed_map("state2");
+        |_capability, info, state_handle| {
+            let operator = checkpoints.register("Q3 Join", scope.activator_for(&info.address[..]));
+            let mut join = Join {
+                nt,
+                ttl_ns,
+                state1: ManagedMultiMap::new(state_handle.get_managed_map("state1")),
+                state2: state_handle.get_managed_map("state2"),
+                expirations1: ManagedTimers::new(
+                    state_handle.get_managed_map("expirations1"),
+                    state_handle.get_managed_map("expirations1_index"),
+                    TIMER_GRANULARITY,
+                ),
+                expirations2: ManagedTimers::new(
+                    state_handle.get_managed_map("expirations2"),
+                    state_handle.get_managed_map("expirations2_index"),
+                    TIMER_GRANULARITY,
+                ),
+                sellers: BinIndex::new(state_handle.get_managed_map("sellers")),
+                state1_size,
+                state2_size,
+            };
+            // Restore the sellers of each bin with the routing, renewing their expirations.
+            if let Some(snapshot) = checkpoints.restore::<JoinSnapshot>(&operator) {
+                migrator.restore(snapshot.routing);
+                for bin in snapshot.bins.into_iter() {
+                    join.install(bin);
+                }
+            }
+            let mut held: Held<Auction, Person> = Held::new();
+            let mut joined = Vec::new();
 
             move |input1, input2, output| {
-                // Process each input auction.
+                // Process each input auction, and install the bins moving to this worker.
                 input1.for_each(|time, data| {
                     data.swap(&mut auctions_buffer);
-                    let mut session = output.session(&time);
-                    for auction in auctions_buffer.drain(..) {
-                        if let Some(person) = state2.get(&auction.seller) {
-                            session.give((
-                                person.name.clone(),
-                                person.city.clone(),
-                                person.state.clone(),
-                                auction.id,
-                            ));
+                    let capability: Capability<usize> = time.retain();
+                    let mut auctions = Vec::new();
+                    for (_, routed) in auctions_buffer.drain(..) {
+                        match routed {
+                            Routed::Record(bin, epoch, auction) => {
+                                if migrator.is_installed(bin, epoch) {
+                                    auctions.push(auction);
+                                } else {
+                                    held.hold1(bin, &capability, auction);
+                                }
+                            }
+                            Routed::Bin(bin, epoch, state) => {
+                                join.install(state);
+                                migrator.install(bin, epoch);
+                                let (auctions, people) = held.release(bin);
+                                for (capability, auction) in auctions.into_iter() {
+                                    join.auctions(vec![auction], &mut joined);
+                                    output.session(&capability).give_iterator(joined.drain(..));
+                                }
+                                for (capability, person) in people.into_iter() {
+                                    join.people(vec![person], &mut joined);
+                                    output.session(&capability).give_iterator(joined.drain(..));
+                                }
+                            }
                         }
-                        let seller = auction.seller;
-                        //state1.rmw(auction.seller, vec![auction]);
-                        let mut seller_auctions = state1.remove(&seller).unwrap_or(Vec::new());
-                        seller_auctions.push(auction);
-                        state1.insert(seller, seller_auctions);
                     }
+                    join.auctions(auctions, &mut joined);
+                    output.session(&capability).give_iterator(joined.drain(..));
                 });
 
                 // Process each input person.
                 input2.for_each(|time, data| {
                     data.swap(&mut people_buffer);
-                    let mut session = output.session(&time);
-                    for person in people_buffer.drain(..) {
-                        if let Some(auctions) = state1.get(&person.id) {
-                            for auction in auctions.iter() {
-                                session.give((
-                                    person.name.clone(),
-                                    person.city.clone(),
-                                    person.state.clone(),
-                                    auction.id,
-                                ));
+                    let capability: Capability<usize> = time.retain();
+                    let mut people = Vec::new();
+                    for (_, routed) in people_buffer.drain(..) {
+                        match routed {
+                            Routed::Record(bin, epoch, person) => {
+                                if migrator.is_installed(bin, epoch) {
+                                    people.push(person);
+                                } else {
+                                    held.hold2(bin, &capability, person);
+                                }
                             }
+                            Routed::Bin(..) => unreachable!("bins are sent with the auctions"),
                         }
-                        state2.insert(person.id, person);
                     }
+                    join.people(people, &mut joined);
+                    output.session(&capability).give_iterator(joined.drain(..));
                 });
+
+                let complete1 = input1
+                    .frontier
+                    .frontier()
+                    .get(0)
+                    .cloned()
+                    .unwrap_or(usize::max_value());
+                let complete2 = input2
+                    .frontier
+                    .frontier()
+                    .get(0)
+                    .cloned()
+                    .unwrap_or(usize::max_value());
+                let complete = std::cmp::min(complete1, complete2);
+
+                // Evict the state that expired before the frontier.
+                join.evict(complete);
+
+                // Extract the bins leaving this worker once their records before the move are
+                // processed.
+                for (epoch, bin) in migrator.leaving(complete) {
+                    migrator.extracted(epoch, bin, join.extract(bin));
+                }
+
+                if checkpoints.is_due(&operator, complete) {
+                    let snapshot = JoinSnapshot {
+                        routing: migrator.routing(),
+                        bins: (0..BINS).map(|bin| join.capture(bin)).collect(),
+                    };
+                    checkpoints.write(&operator, &snapshot);
+                }
             }
         },
     )
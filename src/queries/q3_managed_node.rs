@@ -1,4 +1,3 @@
-use timely::dataflow::channels::pact::Exchange;
 use timely::dataflow::operators::{Filter, Operator};
 use timely::dataflow::{Scope, Stream};
 use timely::state::StateHandle;
@@ -6,14 +5,17 @@ use timely::state::backends::FASTERNodeBackend;
 use timely::state::primitives::ManagedMap;
 
 use crate::event::{Auction, Person};
+use crate::primitives::{Batch, ManagedMultiMap, ManagedTimers};
 
+use crate::queries::q3::ttl_expiry;
 use crate::queries::{NexmarkInput, NexmarkTimer};
 
 pub fn q3_managed_node<S: Scope<Timestamp = usize>>(
     input: &NexmarkInput,
-    _nt: NexmarkTimer,
+    nt: NexmarkTimer,
     scope: &mut S,
-    node_state_handle: &StateHandle<FASTERNodeBackend>
+    node_state_handle: &StateHandle<FASTERNodeBackend>,
+    ttl_ns: Option<usize>,
 ) -> Stream<S, (String, String, String, usize)> {
     let auctions = input.auctions(scope).filter(|a| a.category == 10);
 
@@ -24,24 +26,37 @@ pub fn q3_managed_node<S: Scope<Timestamp = usize>>(
     let mut auctions_buffer = vec![];
     let mut people_buffer = vec![];
 
-    let mut state1: Box<ManagedMap<usize, Vec<Auction>>> =
-        node_state_handle.get_managed_map("state1");
+    let mut state1: ManagedMultiMap<usize, Auction> =
+        ManagedMultiMap::new(node_state_handle.get_managed_map("state1"));
     let mut state2: Box<ManagedMap<usize, Person>> = node_state_handle.get_managed_map("state2");
+    // Timers at the expiry times of auctions, for their sellers, and of people. The timers are
+    // shared by the workers of the node, and each worker fires those in the slots it set timers in.
+    let mut expirations1: ManagedTimers<usize> = ManagedTimers::new(
+        node_state_handle.get_managed_map("expirations1"),
+        node_state_handle.get_managed_map("expirations1_index"),
+        1_000_000,
+    );
+    let mut expirations2: ManagedTimers<usize> = ManagedTimers::new(
+        node_state_handle.get_managed_map("expirations2"),
+        node_state_handle.get_managed_map("expirations2_index"),
+        1_000_000,
+    );
 
-    auctions.binary(
+    auctions.binary_frontier(
         &people,
-        Exchange::new(|a: &Auction| a.seller as u64 / 100),
-        Exchange::new(|p: &Person| p.id as u64 / 100),
+        input.partitioner.exchange("Q3 Join/auctions", |a: &Auction| a.seller as u64 / 100),
+        input.partitioner.exchange("Q3 Join/people", |p: &Person| p.id as u64 / 100),
         "Q3 Join",
         move |_capability, _info, _state_handle| {
-
             move |input1, input2, output| {
                 // Process each input auction.
                 input1.for_each(|time, data| {
                     data.swap(&mut auctions_buffer);
                     let mut session = output.session(&time);
+                    let sellers = auctions_buffer.iter().map(|auction| auction.seller);
+                    let mut people = Batch::read(&mut state2, sellers);
                     for auction in auctions_buffer.drain(..) {
-                        if let Some(person) = state2.get(&auction.seller) {
+                        if let Some(person) = people.get(&auction.seller) {
                             session.give((
                                 person.name.clone(),
                                 person.city.clone(),
@@ -50,10 +65,11 @@ pub fn q3_managed_node<S: Scope<Timestamp = usize>>(
                             ));
                         }
                         let seller = auction.seller;
-                        //state1.rmw(auction.seller, vec![auction]);
-                        let mut seller_auctions = state1.remove(&seller).unwrap_or(Vec::new());
-                        seller_auctions.push(auction);
-                        state1.insert(seller, seller_auctions);
+                        if let Some(ttl_ns) = ttl_ns {
+                            let expiry = ttl_expiry(nt, auction.date_time, ttl_ns);
+                            expirations1.register(expiry, seller);
+                        }
+                        state1.append(seller, auction);
                     }
                 });
 
@@ -61,8 +77,10 @@ pub fn q3_managed_node<S: Scope<Timestamp = usize>>(
                 input2.for_each(|time, data| {
                     data.swap(&mut people_buffer);
                     let mut session = output.session(&time);
+                    let mut sellers = state1.batch(people_buffer.iter().map(|person| person.id));
+                    let mut people = Batch::read(&mut state2, Vec::new());
                     for person in people_buffer.drain(..) {
-                        if let Some(auctions) = state1.get(&person.id) {
+                        if let Some(auctions) = sellers.get(&person.id) {
                             for auction in auctions.iter() {
                                 session.give((
                                     person.name.clone(),
@@ -72,9 +90,42 @@ pub fn q3_managed_node<S: Scope<Timestamp = usize>>(
                                 ));
                             }
                         }
-                        state2.insert(person.id, person);
+                        if let Some(ttl_ns) = ttl_ns {
+                            let expiry = ttl_expiry(nt, person.date_time, ttl_ns);
+                            expirations2.register(expiry, person.id);
+                        }
+                        people.insert(person.id, person);
                     }
                 });
+
+                // Evict the state that expired before the frontier.
+                if let Some(ttl_ns) = ttl_ns {
+                    let complete1 = input1
+                        .frontier
+                        .frontier()
+                        .get(0)
+                        .cloned()
+                        .unwrap_or(usize::max_value());
+                    let complete2 = input2
+                        .frontier
+                        .frontier()
+                        .get(0)
+                        .cloned()
+                        .unwrap_or(usize::max_value());
+                    let complete = std::cmp::min(complete1, complete2);
+
+                    for (time, seller) in expirations1.fire(complete) {
+                        if let Some(mut auctions) = state1.remove(&seller) {
+                            auctions.retain(|auction| {
+                                ttl_expiry(nt, auction.date_time, ttl_ns) > time
+                            });
+                            state1.replace(seller, auctions);
+                        }
+                    }
+                    for (_, person) in expirations2.fire(complete) {
+                        state2.remove(&person);
+                    }
+                }
             }
         },
     )
@@ -1,4 +1,3 @@
-use timely::dataflow::channels::pact::Exchange;
 use timely::dataflow::operators::{Map, Operator};
 use timely::dataflow::{Scope, Stream};
 
@@ -13,7 +12,9 @@ pub fn q4<S: Scope<Timestamp = usize>>(
         .closed_auctions(scope)
         .map(|(a, b)| (a.category, b.price))
         .unary(
-            Exchange::new(|x: &(usize, usize)| x.0 as u64),
+            input
+                .partitioner
+                .exchange("Q4 Average", |x: &(usize, usize)| x.0 as u64),
             "Q4 Average",
             |_cap, _info, _state_handle| {
                 // Stores category -> (total, count)
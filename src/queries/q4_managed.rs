@@ -1,11 +1,11 @@
-use timely::dataflow::channels::pact::Exchange;
+use std::collections::HashMap;
 use timely::dataflow::operators::{Map, Operator};
 use timely::dataflow::{Scope, Stream};
 
 use crate::queries::{NexmarkInput, NexmarkTimer};
 use faster_rs::FasterRmw;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 struct SumWithCount(usize, usize);
 
 impl FasterRmw for SumWithCount {
@@ -23,7 +23,9 @@ pub fn q4_managed<S: Scope<Timestamp = usize>>(
         .closed_auctions(scope)
         .map(|(a, b)| (a.category, b.price))
         .unary(
-            Exchange::new(|x: &(usize, usize)| x.0 as u64),
+            input
+                .partitioner
+                .exchange("Q4 Average", |x: &(usize, usize)| x.0 as u64),
             "Q4 Average",
             |_cap, _info, state_handle| {
                 // Stores category -> (total, count)
@@ -32,13 +34,23 @@ pub fn q4_managed<S: Scope<Timestamp = usize>>(
                 move |input, output| {
                     input.for_each(|time, data| {
                         let mut session = output.session(&time);
+                        // Stores category -> (current sum and count, sum and count of this batch)
+                        let mut sums = HashMap::new();
                         for (category, price) in data.iter().cloned() {
-                            let mut current_sum_count =
-                                state.remove(&category).unwrap_or(SumWithCount(0, 0));
-                            current_sum_count.0 += price;
-                            current_sum_count.1 += 1;
-                            session.give((category, current_sum_count.0 / current_sum_count.1));
-                            state.insert(category, current_sum_count);
+                            let (current, delta) = sums.entry(category).or_insert_with(|| {
+                                let current = state.get(&category);
+                                let current = current.map_or(SumWithCount(0, 0), |c| (*c).clone());
+                                (current, SumWithCount(0, 0))
+                            });
+                            current.0 += price;
+                            current.1 += 1;
+                            delta.0 += price;
+                            delta.1 += 1;
+                            session.give((category, current.0 / current.1));
+                        }
+                        // Merge each category's batch into its sum with a single write.
+                        for (category, (_, delta)) in sums.into_iter() {
+                            state.rmw(category, delta);
                         }
                     })
                 }
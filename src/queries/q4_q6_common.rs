@@ -1,11 +1,11 @@
 use std::collections::HashMap;
-use timely::dataflow::channels::pact::Exchange;
 use timely::dataflow::operators::{Capability, Operator};
 use timely::dataflow::{Scope, Stream};
 
 use crate::event::{Auction, Bid};
 
 use crate::queries::{NexmarkInput, NexmarkTimer};
+use crate::state_sizes::bytes_of;
 
 pub fn q4_q6_common<S: Scope<Timestamp = usize>>(
     input: &NexmarkInput,
@@ -15,10 +15,17 @@ pub fn q4_q6_common<S: Scope<Timestamp = usize>>(
     let bids = input.bids(scope);
     let auctions = input.auctions(scope);
 
+    let state_size = input.state_sizes.register("Q4 Auction close/state");
+    let expirations_size = input.state_sizes.register("Q4 Auction close/expirations");
+
     bids.binary_frontier(
         &auctions,
-        Exchange::new(|b: &Bid| b.auction as u64),
-        Exchange::new(|a: &Auction| a.id as u64),
+        input
+            .partitioner
+            .exchange("Q4 Auction close/bids", |b: &Bid| b.auction as u64),
+        input
+            .partitioner
+            .exchange("Q4 Auction close/auctions", |a: &Auction| a.id as u64),
         "Q4 Auction close",
         |_capability, _info, _state_handle| {
             let mut state: HashMap<_, (Option<_>, Vec<Bid>)> = std::collections::HashMap::new();
@@ -149,6 +156,15 @@ pub fn q4_q6_common<S: Scope<Timestamp = usize>>(
                 } else {
                     capability = None;
                 }
+
+                if state_size.is_due() {
+                    let bytes = state.iter().map(|(k, v)| bytes_of(k) + bytes_of(v)).sum();
+                    state_size.set(state.len(), bytes);
+                }
+                if expirations_size.is_due() {
+                    let bytes = opens.iter().map(std::mem::size_of_val).sum();
+                    expirations_size.set(opens.len(), bytes);
+                }
             }
         },
     )
@@ -1,10 +1,14 @@
 use std::collections::HashMap;
-use timely::dataflow::channels::pact::Exchange;
 use timely::dataflow::operators::{Capability, Operator};
 use timely::dataflow::{Scope, Stream};
+use timely::scheduling::Scheduler;
 use timely::state::primitives::ManagedMap;
+use timely::worker::AsWorker;
 
 use crate::event::{Auction, Bid};
+use crate::migration::{bin_of, BinIndex, Held, Migrator, Routed, Routing, BINS};
+use crate::primitives::ManagedTimers;
+use crate::state_sizes::StateSize;
 
 use crate::queries::{NexmarkInput, NexmarkTimer};
 use faster_rs::FasterRmw;
@@ -15,16 +19,174 @@ fn is_valid_bid(bid: &Bid, auction: &Auction) -> bool {
         && bid.date_time < auction.expires
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, Abomonation)]
 struct AuctionBids(Option<Auction>, Vec<Bid>);
 
 impl FasterRmw for AuctionBids {
-    fn rmw(&self, _modification: Self) -> Self {
-        unimplemented!()
+    /// Adds the auction and bids of `modification`. Once the auction is known, only its first
+    /// highest valid bid is kept.
+    fn rmw(&self, modification: Self) -> Self {
+        let auction = modification.0.or_else(|| self.0.clone());
+        let mut bids = self.1.clone();
+        bids.extend(modification.1);
+        if let Some(ref auction) = auction {
+            bids.retain(|bid| is_valid_bid(bid, auction));
+            // `max_by_key` returns the last maximum, so search from the back for the first.
+            if let Some(bid) = bids.iter().rev().max_by_key(|bid| bid.price).cloned() {
+                bids = vec![bid];
+            }
+        }
+        AuctionBids(auction, bids)
     }
 }
 
+/// The state of the auctions of a bin, as it moves between workers.
+type AuctionsBin = Vec<(usize, AuctionBids)>;
+
+/// Snapshot of the auction close and its routing, for checkpoints.
+#[derive(Serialize, Deserialize)]
+struct AuctionCloseSnapshot {
+    routing: Routing,
+    bins: Vec<AuctionsBin>,
+}
+
+struct AuctionClose {
+    nt: NexmarkTimer,
+    state: Box<ManagedMap<usize, AuctionBids>>,
+    // Stores the expiration time of each auction, in slots of a millisecond of timestamps
+    expirations: ManagedTimers<usize>,
+    capability: Option<Capability<usize>>,
+    /// Stores bin -> auctions with state, to extract the bin
+    auctions: BinIndex,
+    state_size: StateSize,
+    expirations_size: StateSize,
+}
+
+impl AuctionClose {
+    /// Records `bids`, merging the bids of each auction with a single write. The merge keeps only
+    /// the highest valid bid once the auction is known.
+    /// NB: We don't summarize as the max, because we don't know which are valid.
+    fn bids(&mut self, bids: Vec<Bid>) {
+        let mut merged: HashMap<usize, Vec<Bid>> = HashMap::new();
+        for bid in bids.into_iter() {
+            merged.entry(bid.auction).or_default().push(bid);
+        }
+        for (auction_id, bids) in merged.into_iter() {
+            self.merge(auction_id, AuctionBids(None, bids));
+        }
+    }
+
+    /// Records `auction`, with a timer at its expiration held by `capability`.
+    fn auction(&mut self, capability: &Capability<usize>, auction: Auction) {
+        let auction_id = auction.id;
+        self.register(capability, expiration_of(self.nt, &auction), auction_id);
+        self.merge(auction_id, AuctionBids(Some(auction), Vec::new()));
+    }
+
+    fn register(&mut self, capability: &Capability<usize>, expiration: usize, auction_id: usize) {
+        if self.capability.as_ref().map_or(true, |c| *c.time() > expiration) {
+            self.capability = Some(capability.delayed(&expiration));
+        }
+        self.expirations_size.added(1, &(expiration, auction_id));
+        self.expirations.register(expiration, auction_id);
+    }
+
+    fn merge(&mut self, auction_id: usize, entry: AuctionBids) {
+        let state = &mut self.state;
+        self.state_size.merged(|| state.get(&auction_id), &entry);
+        state.rmw(auction_id, entry);
+        self.auctions.insert(bin_of(auction_id as u64), auction_id, |auction_id| state.get(&auction_id).is_some());
+    }
 
+    /// Closes the auctions whose expiration is before `complete`, returning the winning bids.
+    fn close(&mut self, complete: usize) -> Vec<(Auction, Bid)> {
+        let mut closed = Vec::new();
+        for (expiration, auction_id) in self.expirations.fire(complete) {
+            self.expirations_size.removed(1, &(expiration, auction_id));
+            if let Some(auction_bids) = self.state.remove(&auction_id) {
+                self.state_size.removed(1, &auction_bids);
+                self.unindex(auction_id);
+                if let AuctionBids(Some(auction), bids) = auction_bids {
+                    if let Some(bid) = bids.into_iter().next() {
+                        closed.push((auction, bid));
+                    }
+                }
+            }
+        }
+        closed
+    }
+
+    /// Holds the capability only as long as timers are set.
+    fn downgrade(&mut self) {
+        match self.expirations.lower_bound() {
+            Some(time) => {
+                if let Some(ref mut capability) = self.capability {
+                    let time = std::cmp::max(time, *capability.time());
+                    capability.downgrade(&time);
+                }
+            }
+            None => self.capability = None,
+        }
+    }
+
+    fn unindex(&mut self, auction_id: usize) {
+        let state = &mut self.state;
+        self.auctions.remove(bin_of(auction_id as u64), |auction_id| state.get(&auction_id).is_some());
+    }
+
+    /// Removes and returns the state of the auctions of `bin`, cancelling their timers.
+    fn extract(&mut self, bin: usize) -> AuctionsBin {
+        let mut extracted = Vec::new();
+        for auction_id in self.auctions.take(bin) {
+            if let Some(auction_bids) = self.state.remove(&auction_id) {
+                self.state_size.removed(1, &auction_bids);
+                if let Some(ref auction) = auction_bids.0 {
+                    let expiration = expiration_of(self.nt, auction);
+                    if self.expirations.cancel(expiration, &auction_id) {
+                        self.expirations_size.removed(1, &(expiration, auction_id));
+                    }
+                }
+                extracted.push((auction_id, auction_bids));
+            }
+        }
+        extracted
+    }
+
+    /// The state of the auctions of `bin`, which stays in place, for checkpoints.
+    fn capture(&mut self, bin: usize) -> AuctionsBin {
+        let state = &mut self.state;
+        let auctions = self.auctions.keys(bin, |auction_id| state.get(&auction_id).is_some());
+        auctions
+            .into_iter()
+            .filter_map(|auction_id| {
+                let auction_bids = self.state.get(&auction_id)?;
+                Some((auction_id, (*auction_bids).clone()))
+            })
+            .collect()
+    }
+
+    /// Installs the state of the auctions of a bin extracted at another worker, with timers held
+    /// by `capability`.
+    fn install(&mut self, capability: &Capability<usize>, bin: AuctionsBin) {
+        for (auction_id, auction_bids) in bin.into_iter() {
+            if let Some(ref auction) = auction_bids.0 {
+                self.register(capability, expiration_of(self.nt, auction), auction_id);
+            }
+            self.merge(auction_id, auction_bids);
+        }
+    }
+}
+
+/// The time at which `auction` closes.
+fn expiration_of(nt: NexmarkTimer, auction: &Auction) -> usize {
+    nt.from_nexmark_time(auction.expires)
+}
+
+/// Closes auctions at their expiration with their highest valid bid, keeping the state in managed
+/// maps.
+///
+/// Auctions are routed in bins that the control stream can migrate to other workers, with their
+/// state and timers, see `crate::migration`.
 pub fn q4_q6_common_managed<S: Scope<Timestamp = usize>>(
     input: &NexmarkInput,
     nt: NexmarkTimer,
@@ -33,88 +195,124 @@ pub fn q4_q6_common_managed<S: Scope<Timestamp = usize>>(
     let bids = input.bids(scope);
     let auctions = input.auctions(scope);
 
-    bids.binary_notify(
+    let control = input.control(scope);
+    let migrator: Migrator<AuctionsBin> = Migrator::new(scope.index(), scope.peers());
+    // The bids' router is created first, so it sends the bins.
+    let bids = migrator.route(&bids, &control, "Q4 Route bids", |b: &Bid| b.auction as u64);
+    let auctions = migrator.route(&auctions, &control, "Q4 Route auctions", |a: &Auction| a.id as u64);
+
+    let state_size = input.state_sizes.register("Q4 Auction close/state");
+    let expirations_size = input.state_sizes.register("Q4 Auction close/expirations");
+
+    let mut bids_buffer = vec![];
+    let mut auctions_buffer = vec![];
+
+    let checkpoints = input.checkpoints.clone();
+    let scope = scope.clone();
+
+    bids.binary_frontier(
         &auctions,
-        Exchange::new(|b: &Bid| b.auction as u64),
-        Exchange::new(|a: &Auction| a.id as u64),
+        input.partitioner.exchange_to("Q4 Auction close/bids", |b: &(usize, Routed<Bid, AuctionsBin>)| b.0),
+        input.partitioner.exchange_to("Q4 Auction close/auctions", |a: &(usize, Routed<Auction, AuctionsBin>)| a.0),
         "Q4 Auction close",
-        None,
-        move |input1, input2, output, notificator, state_handle| {
-            let mut state: Box<ManagedMap<usize, AuctionBids>> = state_handle.get_managed_map("state");
-            let mut expirations: Box<ManagedMap<usize, Vec<Auction>>> = state_handle.get_managed_map("expirations");
-            // Record each bid.
-            // NB: We don't summarize as the max, because we don't know which are valid.
-            input1.for_each(|time, data| {
-                for bid in data.iter().cloned() {
-                    let auction_bids = state.remove(&bid.auction);
-                    match auction_bids {
-                        Some(mut entry) => {
-                            let auction_id = bid.auction;
-                            if let Some(auction) = entry.0.clone() {
-                                if is_valid_bid(&bid, &auction) {
-                                    // bid must fall between auction creation and expiration
-                                    if let Some(existing) = entry.1.get(0) {
-                                        if existing.price < bid.price {
-                                            entry.1[0] = bid;
-                                        }
-                                    } else {
-                                        entry.1.push(bid);
-                                    }
+        |capability, info, state_handle| {
+            let operator = checkpoints.register("Q4 Auction close", scope.activator_for(&info.address[..]));
+            let mut close = AuctionClose {
+                nt,
+                state: state_handle.get_managed_map("state"),
+                expirations: ManagedTimers::new(
+                    state_handle.get_managed_map("expirations"),
+                    state_handle.get_managed_map("expirations_index"),
+                    1_000_000,
+                ),
+                capability: None,
+                auctions: BinIndex::new(state_handle.get_managed_map("auctions")),
+                state_size,
+                expirations_size,
+            };
+            // Restore the auctions of each bin with the routing, and their timers.
+            if let Some(snapshot) = checkpoints.restore::<AuctionCloseSnapshot>(&operator) {
+                migrator.restore(snapshot.routing);
+                for bin in snapshot.bins.into_iter() {
+                    close.install(&capability, bin);
+                }
+            }
+            let mut held: Held<Bid, Auction> = Held::new();
+
+            move |input1, input2, output| {
+                // Record each bid, and install the bins moving to this worker.
+                input1.for_each(|time, data| {
+                    data.swap(&mut bids_buffer);
+                    let capability: Capability<usize> = time.retain();
+                    let mut bids = Vec::new();
+                    for (_, routed) in bids_buffer.drain(..) {
+                        match routed {
+                            Routed::Record(bin, epoch, bid) => {
+                                if migrator.is_installed(bin, epoch) {
+                                    bids.push(bid);
+                                } else {
+                                    held.hold1(bin, &capability, bid);
+                                }
+                            }
+                            Routed::Bin(bin, epoch, state) => {
+                                close.install(&capability, state);
+                                migrator.install(bin, epoch);
+                                let (bids, auctions) = held.release(bin);
+                                close.bids(bids.into_iter().map(|(_, bid)| bid).collect());
+                                for (capability, auction) in auctions.into_iter() {
+                                    close.auction(&capability, auction);
                                 }
                             }
-                            state.insert(auction_id, entry);
-                        }
-                        None => {
-                            state.insert(bid.auction, AuctionBids(None, vec![bid]));
                         }
                     }
-                }
-            });
-
-            // Record each auction.
-            input2.for_each(|time, data| {
-                for auction in data.iter().cloned() {
-                    notificator.notify_at(time.delayed(&nt.from_nexmark_time(auction.expires)));
-                    let auction_id = auction.id;
-                    expirations.rmw(nt.from_nexmark_time(auction.expires), vec![auction.clone()]);
-                    let mut auction_bids = state.remove(&auction_id).unwrap_or(AuctionBids(None, Vec::new()));
-                    auction_bids.0 = Some(auction);
-                    if let Some(bid) = auction_bids.1.iter().max_by_key(|bid| bid.price).cloned() {
-                        auction_bids.1[0] = bid;
-                    }
-                    state.insert(auction_id, auction_bids);
-                }
-            });
-
-            notificator.for_each(|cap, _, _| {
-                let mut session = output.session(&cap);
-                for auction in expirations.remove(cap.time()).expect("Must exist") {
-                    let auction_bids = state.remove(&auction.id);
-                    if let Some(mut auction_bids) = auction_bids {
-                        let insert = match auction_bids.0 {
-                            None => {
-                                auction_bids.1.retain(|bid| bid.date_time > nt.to_nexmark_time(*cap.time()));
-                                !auction_bids.1.is_empty()
-                            },
-                            Some(ref auction) => {
-                                if auction.expires == nt.to_nexmark_time(*cap.time()) {
-                                    if auction_bids.1.len() > 0 {
-                                        session.give(
-                                            (auction.clone(), auction_bids.1[0].clone()),
-                                        );
-                                    }
-                                    false
+                    close.bids(bids);
+                });
+
+                // Record each auction, with a timer at its expiration.
+                input2.for_each(|time, data| {
+                    data.swap(&mut auctions_buffer);
+                    let capability: Capability<usize> = time.retain();
+                    for (_, routed) in auctions_buffer.drain(..) {
+                        match routed {
+                            Routed::Record(bin, epoch, auction) => {
+                                if migrator.is_installed(bin, epoch) {
+                                    close.auction(&capability, auction);
                                 } else {
-                                    true
+                                    held.hold2(bin, &capability, auction);
                                 }
-                            },
-                        };
-                        if insert {
-                            state.insert(auction.id, auction_bids);
+                            }
+                            Routed::Bin(..) => unreachable!("bins are sent with the bids"),
                         }
                     }
+                });
+
+                let complete1 = input1.frontier.frontier().get(0).cloned().unwrap_or(usize::max_value());
+                let complete2 = input2.frontier.frontier().get(0).cloned().unwrap_or(usize::max_value());
+                let complete = std::cmp::min(complete1, complete2);
+
+                // Close the auctions whose expiration the frontier passed.
+                let closed = close.close(complete);
+                if let Some(ref capability) = close.capability {
+                    output.session(capability).give_iterator(closed.into_iter());
+                }
+
+                // Extract the bins leaving this worker once their records before the move are
+                // processed.
+                for (epoch, bin) in migrator.leaving(complete) {
+                    let state = close.extract(bin);
+                    migrator.extracted(epoch, bin, state);
                 }
-            });
+
+                if checkpoints.is_due(&operator, complete) {
+                    let snapshot = AuctionCloseSnapshot {
+                        routing: migrator.routing(),
+                        bins: (0..BINS).map(|bin| close.capture(bin)).collect(),
+                    };
+                    checkpoints.write(&operator, &snapshot);
+                }
+
+                close.downgrade();
+            }
         },
     )
 }
@@ -1,5 +1,4 @@
 use std::collections::VecDeque;
-use timely::dataflow::channels::pact::Exchange;
 use timely::dataflow::operators::{Map, Operator};
 use timely::dataflow::{Scope, Stream};
 
@@ -14,7 +13,9 @@ pub fn q6<S: Scope<Timestamp = usize>>(
         .closed_auctions(scope)
         .map(|(_a, b)| (b.bidder, b.price))
         .unary(
-            Exchange::new(|x: &(usize, usize)| x.0 as u64),
+            input
+                .partitioner
+                .exchange("Q6 Average", |x: &(usize, usize)| x.0 as u64),
             "Q6 Average",
             |_cap, _info, _state_handle| {
                 // Store bidder -> [prices; 10]
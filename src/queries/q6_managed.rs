@@ -1,17 +1,31 @@
 use faster_rs::FasterRmw;
-use std::collections::VecDeque;
-use timely::dataflow::channels::pact::Exchange;
+use std::collections::{HashMap, VecDeque};
 use timely::dataflow::operators::{Map, Operator};
 use timely::dataflow::{Scope, Stream};
 
 use crate::queries::{NexmarkInput, NexmarkTimer};
 
-#[derive(Serialize, Deserialize)]
+/// Number of recent prices averaged per bidder.
+const RECENT_PRICES: usize = 10;
+
+/// The most recent prices of a bidder, most recent first.
+#[derive(Clone, Serialize, Deserialize)]
 struct Prices(VecDeque<usize>);
 
+impl Prices {
+    fn push(&mut self, price: usize) {
+        self.0.push_front(price);
+        self.0.truncate(RECENT_PRICES);
+    }
+}
+
 impl FasterRmw for Prices {
-    fn rmw(&self, _modification: Self) -> Self {
-        panic!("RMW on VecDeque<T> is unsafe");
+    /// The modification holds more recent prices, which displace the oldest stored ones.
+    fn rmw(&self, modification: Self) -> Self {
+        let mut prices = modification.0;
+        prices.extend(self.0.iter().cloned());
+        prices.truncate(RECENT_PRICES);
+        Prices(prices)
     }
 }
 
@@ -24,7 +38,9 @@ pub fn q6_managed<S: Scope<Timestamp = usize>>(
         .closed_auctions(scope)
         .map(|(_a, b)| (b.bidder, b.price))
         .unary(
-            Exchange::new(|x: &(usize, usize)| x.0 as u64),
+            input
+                .partitioner
+                .exchange("Q6 Average", |x: &(usize, usize)| x.0 as u64),
             "Q6 Average",
             |_cap, _info, state_handle| {
                 // Store bidder -> [prices; 10]
@@ -33,16 +49,23 @@ pub fn q6_managed<S: Scope<Timestamp = usize>>(
                 move |input, output| {
                     input.for_each(|time, data| {
                         let mut session = output.session(&time);
+                        // Stores bidder -> (current prices, prices of this batch)
+                        let mut prices = HashMap::new();
                         for (bidder, price) in data.iter().cloned() {
-                            let mut entry =
-                                state.remove(&bidder).unwrap_or(Prices(VecDeque::new())).0;
-                            if entry.len() >= 10 {
-                                entry.pop_back();
-                            }
-                            entry.push_front(price);
-                            let sum: usize = entry.iter().sum();
-                            session.give((bidder, sum / entry.len()));
-                            state.insert(bidder, Prices(entry));
+                            let (current, delta) = prices.entry(bidder).or_insert_with(|| {
+                                let current = state.get(&bidder);
+                                let current =
+                                    current.map_or(Prices(VecDeque::new()), |p| (*p).clone());
+                                (current, Prices(VecDeque::new()))
+                            });
+                            current.push(price);
+                            delta.push(price);
+                            let sum: usize = current.0.iter().sum();
+                            session.give((bidder, sum / current.0.len()));
+                        }
+                        // Merge each bidder's batch into its prices with a single write.
+                        for (bidder, (_, delta)) in prices.into_iter() {
+                            state.rmw(bidder, delta);
                         }
                     });
                 }
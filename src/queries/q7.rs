@@ -1,86 +1,54 @@
-use timely::dataflow::channels::pact::{Exchange, Pipeline};
-use timely::dataflow::operators::{Capability, Map, Operator};
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::Map;
 use timely::dataflow::{Scope, Stream};
 
 use crate::event::Date;
+use crate::windows::{self, Backing, Max};
 
 use crate::queries::{NexmarkInput, NexmarkTimer};
 
+/// Finds the highest bid in each window, keeping the window state as selected by `backing`.
 pub fn q7<S: Scope<Timestamp = usize>>(
     input: &NexmarkInput,
     nt: NexmarkTimer,
     scope: &mut S,
     window_size_ns: usize,
-) -> Stream<S, usize> {
-    input
-        .bids(scope)
-        .map(move |b| {
-            (
-                Date::new(((*b.date_time / window_size_ns) + 1) * window_size_ns),
-                b.price,
-            )
-        })
-        .unary_frontier(Pipeline, "Q7 Pre-reduce", |_cap, _info, _state_handle| {
-            // Tracks the worker-local maximal bid for each capability.
-            let mut maxima = Vec::<(Capability<usize>, usize)>::new();
-
-            move |input, output| {
-                input.for_each(|time, data| {
-                    for (window, price) in data.iter().cloned() {
-                        if let Some(position) = maxima
-                            .iter()
-                            .position(|x| *(x.0).time() == nt.from_nexmark_time(window))
-                        {
-                            if maxima[position].1 < price {
-                                maxima[position].1 = price;
-                            }
-                        } else {
-                            maxima.push((time.delayed(&nt.from_nexmark_time(window)), price));
-                        }
-                    }
-                });
-
-                for &(ref capability, price) in maxima.iter() {
-                    if !input.frontier.less_than(capability.time()) {
-                        output
-                            .session(&capability)
-                            .give((*capability.time(), price));
-                    }
-                }
-
-                maxima.retain(|(capability, _)| input.frontier.less_than(capability));
-            }
-        })
-        .unary_frontier(
-            Exchange::new(move |x: &(usize, usize)| (x.0 / window_size_ns) as u64),
-            "Q7 All-reduce",
-            |_cap, _info, _state_handle| {
-                // Tracks the global maximal bid for each capability.
-                let mut maxima = Vec::<(Capability<usize>, usize)>::new();
-
-                move |input, output| {
-                    input.for_each(|time, data| {
-                        for (window, price) in data.iter().cloned() {
-                            if let Some(position) =
-                                maxima.iter().position(|x| *(x.0).time() == window)
-                            {
-                                if maxima[position].1 < price {
-                                    maxima[position].1 = price;
-                                }
-                            } else {
-                                maxima.push((time.delayed(&window), price));
-                            }
-                        }
-                    });
-
-                    for &(ref capability, price) in maxima.iter() {
-                        if !input.frontier.less_than(capability.time()) {
-                            output.session(&capability).give(price);
-                        }
-                    }
-
-                    maxima.retain(|(capability, _)| input.frontier.less_than(capability));
-                }
-            },
-        )
+    lateness_ns: usize,
+    backing: Backing,
+) -> (Stream<S, usize>, Stream<S, Date>) {
+    let prices = input.bids(scope).map(|b| ((), b.date_time, b.price));
+
+    // Track the worker-local maximal bid per window.
+    let (maxima, late): (Stream<S, ((), Date, Max<usize>)>, _) = windows::tumbling(
+        &prices,
+        Pipeline,
+        "Q7 Pre-reduce",
+        input.checkpoints,
+        input.state_sizes,
+        nt,
+        window_size_ns,
+        lateness_ns,
+        backing,
+    );
+
+    // Track the global maximal bid per window.
+    let maxima = windows::combine(
+        &maxima,
+        input
+            .partitioner
+            .exchange("Q7 All-reduce", move |x: &((), Date, Max<usize>)| {
+                (*x.1 / window_size_ns) as u64
+            }),
+        "Q7 All-reduce",
+        input.checkpoints,
+        input.state_sizes,
+        nt,
+        lateness_ns,
+        backing,
+    );
+
+    (
+        maxima.map(|(_, _, max)| max.0),
+        late.map(|(_, date, _)| date),
+    )
 }
@@ -0,0 +1,166 @@
+//! Sizes of named operator state, sampled periodically for the timeline output.
+//!
+//! Operators register each piece of state they keep under a name, and account for its keys and
+//! approximate bytes. Bytes are the size of the serialized form of the state, measured with
+//! `abomonation::measure`. Managed maps can't be iterated, so operators account for what they
+//! add and remove; native collections recompute their size whenever a sample is due.
+
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use abomonation::Abomonation;
+use faster_rs::FasterRmw;
+use streaming_harness::util::ToNanos;
+
+struct Counts {
+    keys: AtomicIsize,
+    bytes: AtomicIsize,
+    /// Whether a new sample was taken since the size was last recomputed.
+    due: AtomicBool,
+}
+
+/// Size of one named piece of state of one operator.
+#[derive(Clone)]
+pub struct StateSize(Option<Arc<Counts>>);
+
+impl StateSize {
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Accounts for `keys` new keys and the bytes of `data` added to the state.
+    pub fn added<T: Abomonation>(&self, keys: usize, data: &T) {
+        if let Some(ref counts) = self.0 {
+            counts.keys.fetch_add(keys as isize, Ordering::Relaxed);
+            counts
+                .bytes
+                .fetch_add(bytes_of(data) as isize, Ordering::Relaxed);
+        }
+    }
+
+    /// Accounts for `keys` removed keys and the bytes of `data` removed from the state.
+    pub fn removed<T: Abomonation>(&self, keys: usize, data: &T) {
+        if let Some(ref counts) = self.0 {
+            counts.keys.fetch_sub(keys as isize, Ordering::Relaxed);
+            counts
+                .bytes
+                .fetch_sub(bytes_of(data) as isize, Ordering::Relaxed);
+        }
+    }
+
+    /// Accounts for merging `modification` into the stored value. The stored value is read with
+    /// `previous` only if the size is tracked.
+    pub fn merged<V, F>(&self, previous: F, modification: &V)
+    where
+        V: FasterRmw + Abomonation + Clone,
+        F: FnOnce() -> Option<Rc<V>>,
+    {
+        if self.is_enabled() {
+            let merged = match previous() {
+                Some(previous) => {
+                    self.removed(1, &*previous);
+                    previous.rmw(modification.clone())
+                }
+                None => modification.clone(),
+            };
+            self.added(1, &merged);
+        }
+    }
+
+    /// Whether the size should be recomputed with `set`, which is once per sample.
+    pub fn is_due(&self) -> bool {
+        self.0
+            .as_ref()
+            .map_or(false, |counts| counts.due.swap(false, Ordering::Relaxed))
+    }
+
+    /// Sets the size of the state.
+    pub fn set(&self, keys: usize, bytes: usize) {
+        if let Some(ref counts) = self.0 {
+            counts.keys.store(keys as isize, Ordering::Relaxed);
+            counts.bytes.store(bytes as isize, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Approximate size of `data` in bytes.
+pub fn bytes_of<T: Abomonation>(data: &T) -> usize {
+    abomonation::measure(data)
+}
+
+/// The registered state of all operators of a process.
+#[derive(Clone)]
+pub struct StateSizes(Option<Arc<Mutex<Vec<(String, Arc<Counts>)>>>>);
+
+impl StateSizes {
+    pub fn new() -> Self {
+        StateSizes(Some(Arc::new(Mutex::new(Vec::new()))))
+    }
+
+    /// State sizes that are not tracked.
+    pub fn disabled() -> Self {
+        StateSizes(None)
+    }
+
+    /// Registers a piece of state. The sizes of state registered under the same name, by the
+    /// same operator on different workers, are reported as one.
+    pub fn register(&self, name: &str) -> StateSize {
+        StateSize(self.0.as_ref().map(|registry| {
+            let counts = Arc::new(Counts {
+                keys: AtomicIsize::new(0),
+                bytes: AtomicIsize::new(0),
+                due: AtomicBool::new(true),
+            });
+            registry
+                .lock()
+                .unwrap()
+                .push((name.to_string(), counts.clone()));
+            counts
+        }))
+    }
+
+    /// Samples the registered state every 500ms until stopped, returning the samples as
+    /// `state_size` lines of elapsed time, name, keys and bytes.
+    pub fn reporter(&self) -> (Arc<AtomicBool>, JoinHandle<Vec<String>>) {
+        let running = Arc::new(AtomicBool::new(true));
+        let registry = self.0.clone().expect("state sizes are disabled");
+        let handle = {
+            let running = running.clone();
+            ::std::thread::spawn(move || {
+                let timer = ::std::time::Instant::now();
+                let mut iteration = 0;
+                let mut samples = Vec::new();
+                while running.load(Ordering::SeqCst) {
+                    let elapsed_ns = timer.elapsed().to_nanos();
+                    let mut sizes = BTreeMap::new();
+                    for (name, counts) in registry.lock().unwrap().iter() {
+                        let size = sizes.entry(name.clone()).or_insert((0, 0));
+                        size.0 += counts.keys.load(Ordering::Relaxed);
+                        size.1 += counts.bytes.load(Ordering::Relaxed);
+                        counts.due.store(true, Ordering::Relaxed);
+                    }
+                    for (name, (keys, bytes)) in sizes.into_iter() {
+                        samples.push(format!(
+                            "state_size\t{}\t{}\t{}\t{}",
+                            elapsed_ns,
+                            name,
+                            keys.max(0),
+                            bytes.max(0)
+                        ));
+                    }
+                    // Sleep until the next sample is due, unless this one ran late.
+                    iteration += 1;
+                    let due = ::std::time::Duration::from_millis(iteration * 500);
+                    if let Some(remaining) = due.checked_sub(timer.elapsed()) {
+                        ::std::thread::sleep(remaining);
+                    }
+                }
+                samples
+            })
+        };
+        (running, handle)
+    }
+}
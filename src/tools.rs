@@ -34,3 +34,129 @@ pub fn statm_reporter() -> ::std::sync::Arc<::std::sync::atomic::AtomicBool> {
     }
     statm_reporter_running
 }
+
+/// Wall-clock time since the Unix epoch, to relate times across processes.
+pub fn unix_time_ns() -> u64 {
+    ::std::time::SystemTime::now()
+        .duration_since(::std::time::UNIX_EPOCH)
+        .expect("clock before the Unix epoch")
+        .to_nanos()
+}
+
+/// Recovery of a worker restarted after an injected failure, in nanoseconds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RecoveryMetrics {
+    /// From the failure until the restarted worker restored its state.
+    pub downtime_ns: u64,
+    /// From the restore until the worker processed the input replayed since the checkpoint.
+    pub catch_up_ns: u64,
+    /// Latency of the first input replayed after the checkpoint, the highest during recovery.
+    pub latency_spike_ns: u64,
+}
+
+/// Migration of key bins to other workers, see `crate::migration`, in nanoseconds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MigrationMetrics {
+    /// Input time the bins moved at.
+    pub at_ns: u64,
+    /// Number of bins moved.
+    pub bins: usize,
+    /// From the input time of the migration until the worker processed its epoch.
+    pub duration_ns: u64,
+}
+
+/// The value of a Timely option such as `-n`/`--processes` in `timely_args`.
+fn timely_option(timely_args: &[String], short: &str, long: &str) -> Option<usize> {
+    let position = timely_args
+        .iter()
+        .position(|arg| arg == short || arg == long)?;
+    let value = timely_args.get(position + 1)?;
+    Some(value.parse().expect("couldn't parse timely option"))
+}
+
+/// Runs the benchmark as local processes and kills process `failed_process` after `fail_after`.
+///
+/// `args` are the benchmark's arguments, and `timely_args` the Timely arguments among them, which
+/// must give the number of processes with `-n`. Timely can't continue without one of its
+/// processes, so all processes are then stopped and restarted from the latest checkpoint in
+/// `checkpoint_dir`, keeping the timeline of the failed run so that replayed input shows its
+/// latency.
+pub fn supervise_failure(
+    args: &[String],
+    timely_args: &[String],
+    fail_after: ::std::time::Duration,
+    failed_process: usize,
+    checkpoint_dir: &::std::path::Path,
+) {
+    use std::process::{Child, Command};
+
+    let processes =
+        timely_option(timely_args, "-n", "--processes").expect("failure injection requires -n");
+    let workers = timely_option(timely_args, "-w", "--workers").unwrap_or(1);
+    assert!(
+        failed_process < processes,
+        "no process {} to fail",
+        failed_process
+    );
+
+    // The processes must not inject failures themselves.
+    let mut base_args = Vec::new();
+    let mut options = args.iter();
+    while let Some(arg) = options.next() {
+        match arg.as_str() {
+            "--inject-failure" | "--failure-process" => {
+                options.next();
+            }
+            "--" => {
+                break;
+            }
+            _ if arg.starts_with("--inject-failure=") || arg.starts_with("--failure-process=") => {}
+            _ => base_args.push(arg.clone()),
+        }
+    }
+    let program = ::std::env::current_exe().expect("can't find the benchmark executable");
+    let spawn = |extra_args: &[String]| -> Vec<Child> {
+        (0..processes)
+            .map(|process| {
+                Command::new(&program)
+                    .args(&base_args)
+                    .args(extra_args)
+                    .arg("--")
+                    .args(timely_args)
+                    .args(&["-p".to_string(), process.to_string()])
+                    .spawn()
+                    .expect("couldn't start process")
+            })
+            .collect()
+    };
+
+    let started_at = unix_time_ns();
+    let mut children = spawn(&["--started-at".to_string(), started_at.to_string()]);
+    ::std::thread::sleep(fail_after);
+
+    children[failed_process]
+        .kill()
+        .expect("couldn't kill process");
+    let failed_at = unix_time_ns();
+    for child in children.iter_mut() {
+        let _ = child.kill();
+        child.wait().expect("couldn't wait for process");
+    }
+
+    let mut extra_args = vec![
+        "--started-at".to_string(),
+        started_at.to_string(),
+        "--failed-at".to_string(),
+        failed_at.to_string(),
+    ];
+    match crate::checkpoint::latest_complete(checkpoint_dir, processes * workers) {
+        Some(checkpoint) => {
+            extra_args.push("--recover-from".to_string());
+            extra_args.push(checkpoint.to_string_lossy().into_owned());
+        }
+        None => eprintln!("No complete checkpoint, restarting from the beginning"),
+    }
+    for mut child in spawn(&extra_args).into_iter() {
+        child.wait().expect("couldn't wait for process");
+    }
+}
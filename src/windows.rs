@@ -0,0 +1,1291 @@
+//! Event-time window operators shared by the windowed queries.
+//!
+//! Each operator assigns records of a `(key, event time, value)` stream to windows, folds them
+//! into an `Aggregate` per key and window, and emits `(key, window end, aggregate)` once the
+//! input frontier closes the window. Window state is kept either in a native `HashMap` or in
+//! managed maps of the dataflow's state backend, as selected by `Backing`.
+//!
+//! Windows stay open for `lateness_ns` after their end. A value that arrives after its window
+//! was emitted but within the lateness updates the window, which is emitted again with the
+//! updated result once the value's time completes. Values that arrive later than that are not
+//! aggregated, and are returned in a separate stream of late data.
+//!
+//! Every operator registers with the worker's `Checkpoints`, writes a snapshot of its open
+//! windows when a checkpoint reaches its input frontier, and restores them when recovering.
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::ops::RangeInclusive;
+
+use abomonation::Abomonation;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use timely::dataflow::channels::pact::{ParallelizationContract, Pipeline};
+use timely::dataflow::operators::{Capability, Map, Operator};
+use timely::dataflow::{Scope, Stream};
+use timely::scheduling::Scheduler;
+use timely::state::primitives::ManagedMap;
+use timely::{Data, ExchangeData};
+
+use crate::checkpoint::Checkpoints;
+use crate::event::Date;
+use crate::primitives::{BucketKeys, ManagedOrderedMap};
+use crate::queries::NexmarkTimer;
+use crate::state_sizes::{StateSize, StateSizes};
+use faster_rs::FasterRmw;
+
+/// An aggregation over the values of a window.
+///
+/// Partial aggregates of the same window are merged with `FasterRmw::rmw`, so managed state can
+/// fold a batch's partial aggregate into the stored one without reading it first.
+pub trait Aggregate<V>: FasterRmw + ExchangeData + Default {
+    fn fold(&mut self, value: V);
+}
+
+/// Counts the values in a window.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Abomonation)]
+pub struct Count(pub usize);
+
+impl Aggregate<()> for Count {
+    fn fold(&mut self, _value: ()) {
+        self.0 += 1;
+    }
+}
+
+/// Sums partial counts, such as those forwarded by `pre_aggregate`.
+impl Aggregate<Count> for Count {
+    fn fold(&mut self, value: Count) {
+        self.0 += value.0;
+    }
+}
+
+impl FasterRmw for Count {
+    fn rmw(&self, modification: Self) -> Self {
+        Count(self.0 + modification.0)
+    }
+}
+
+/// An aggregate whose merge is idempotent: merging a partial aggregate into one that already
+/// includes it leaves it unchanged, so re-fired partial aggregates can be merged again.
+pub trait IdempotentAggregate: FasterRmw {}
+
+/// Keeps the largest value in a window.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Abomonation)]
+pub struct Max<T>(pub T);
+
+impl<T: Ord + ExchangeData + Default + Serialize + DeserializeOwned> Aggregate<T> for Max<T> {
+    fn fold(&mut self, value: T) {
+        if value > self.0 {
+            self.0 = value;
+        }
+    }
+}
+
+impl<T: Ord + Clone + Serialize + DeserializeOwned> FasterRmw for Max<T> {
+    fn rmw(&self, modification: Self) -> Self {
+        std::cmp::max(self.clone(), modification)
+    }
+}
+
+impl<T: Ord + Clone + Serialize + DeserializeOwned> IdempotentAggregate for Max<T> {}
+
+/// The end of the window of `size_ns` that `date` falls in.
+pub fn window_end(date: Date, size_ns: usize) -> Date {
+    Date::new(((*date / size_ns) + 1) * size_ns)
+}
+
+/// Merges `value` into the entry of `key` with `FasterRmw::rmw`, inserting it if absent.
+fn merge_into<K: Eq + Hash, V: FasterRmw>(map: &mut HashMap<K, V>, key: K, value: V) {
+    match map.entry(key) {
+        Entry::Occupied(mut entry) => {
+            let merged = entry.get().rmw(value);
+            entry.insert(merged);
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(value);
+        }
+    }
+}
+
+/// Removes the entries for times before `bound`, in time order.
+fn take_before<T>(entries: &mut BTreeMap<usize, T>, bound: usize) -> BTreeMap<usize, T> {
+    let pending = entries.split_off(&bound);
+    std::mem::replace(entries, pending)
+}
+
+/// Splits an operator's output into its results and its late data.
+fn split<S: Scope, T: Data, L: Data>(
+    stream: &Stream<S, Result<T, L>>,
+) -> (Stream<S, T>, Stream<S, L>) {
+    (stream.flat_map(|x| x.ok()), stream.flat_map(|x| x.err()))
+}
+
+/// Where an operator keeps its window state.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Backing {
+    /// In native `HashMap`s of the operator.
+    Native,
+    /// In managed maps of the dataflow's state backend.
+    Managed,
+}
+
+/// The maps window state is kept in.
+enum Storage<K, V> {
+    Native(HashMap<K, V>),
+    Managed(Box<ManagedMap<K, V>>),
+    /// A managed map that supports range scans, for state keyed by time.
+    Ordered(ManagedOrderedMap<K, V>),
+}
+
+/// Window state, in a native `HashMap` or in a managed map of the dataflow's state backend.
+///
+/// The size of the state is registered with the worker's `StateSizes`. When tracked, each write
+/// first reads the value it replaces, to account for the change in size.
+struct Store<K, V> {
+    storage: Storage<K, V>,
+    size: StateSize,
+}
+
+impl<K, V> Store<K, V>
+where
+    K: 'static + Ord + Hash + Clone + Serialize + DeserializeOwned,
+    V: 'static + FasterRmw + Clone + Abomonation,
+{
+    /// Creates the state `name` of `operator`, getting its managed map from `managed_map`.
+    fn new<F: FnOnce(&str) -> Box<ManagedMap<K, V>>>(
+        backing: Backing,
+        state_sizes: &StateSizes,
+        operator: &str,
+        name: &str,
+        managed_map: F,
+    ) -> Self {
+        let storage = match backing {
+            Backing::Native => Storage::Native(HashMap::new()),
+            Backing::Managed => Storage::Managed(managed_map(name)),
+        };
+        Store {
+            storage,
+            size: state_sizes.register(&format!("{}/{}", operator, name)),
+        }
+    }
+
+    fn get(&mut self, key: &K) -> Option<V> {
+        match &mut self.storage {
+            Storage::Native(map) => map.get(key).cloned(),
+            Storage::Managed(map) => map.get(key).map(|value| (*value).clone()),
+            Storage::Ordered(map) => map.get(key).map(|value| (*value).clone()),
+        }
+    }
+
+    fn contains_key(&mut self, key: &K) -> bool {
+        match &mut self.storage {
+            Storage::Native(map) => map.contains_key(key),
+            Storage::Managed(map) => map.get(key).is_some(),
+            Storage::Ordered(map) => map.contains_key(key),
+        }
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let removed = match &mut self.storage {
+            Storage::Native(map) => map.remove(key),
+            Storage::Managed(map) => map.remove(key),
+            Storage::Ordered(map) => map.remove(key),
+        };
+        if let Some(ref value) = removed {
+            self.size.removed(1, value);
+        }
+        removed
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        if self.size.is_enabled() {
+            if let Some(previous) = self.get(&key) {
+                self.size.removed(1, &previous);
+            }
+            self.size.added(1, &value);
+        }
+        match &mut self.storage {
+            Storage::Native(map) => {
+                map.insert(key, value);
+            }
+            Storage::Managed(map) => map.insert(key, value),
+            Storage::Ordered(map) => map.insert(key, value),
+        }
+    }
+
+    fn rmw(&mut self, key: K, modification: V) {
+        if self.size.is_enabled() {
+            let merged = match self.get(&key) {
+                Some(previous) => {
+                    self.size.removed(1, &previous);
+                    previous.rmw(modification.clone())
+                }
+                None => modification.clone(),
+            };
+            self.size.added(1, &merged);
+        }
+        match &mut self.storage {
+            Storage::Native(map) => merge_into(map, key, modification),
+            Storage::Managed(map) => map.rmw(key, modification),
+            Storage::Ordered(map) => map.rmw(key, modification),
+        }
+    }
+}
+
+impl<V> Store<usize, V>
+where
+    V: 'static + FasterRmw + Clone + Abomonation,
+{
+    /// Creates state keyed by time like `new`, kept in key order when managed, so that ranges of
+    /// times are scanned without looking up the times in between. The managed times are indexed
+    /// in `index_map`, by buckets of `granularity` consecutive times.
+    fn ordered<F, G>(
+        backing: Backing,
+        state_sizes: &StateSizes,
+        operator: &str,
+        name: &str,
+        granularity: usize,
+        managed_map: F,
+        index_map: G,
+    ) -> Self
+    where
+        F: FnOnce(&str) -> Box<ManagedMap<usize, V>>,
+        G: FnOnce(&str) -> Box<ManagedMap<usize, BucketKeys<usize>>>,
+    {
+        let mut store = Store::new(backing, state_sizes, operator, name, managed_map);
+        if let Storage::Managed(map) = store.storage {
+            let index = index_map(&format!("{}_index", name));
+            store.storage = Storage::Ordered(ManagedOrderedMap::new(map, index, granularity));
+        }
+        store
+    }
+
+    /// The entries with times in `range`, in time order.
+    fn range(&mut self, range: RangeInclusive<usize>) -> Vec<(usize, V)> {
+        match &mut self.storage {
+            Storage::Native(map) => {
+                let mut entries: Vec<(usize, V)> = map
+                    .iter()
+                    .filter(|(time, _)| range.contains(time))
+                    .map(|(time, value)| (*time, value.clone()))
+                    .collect();
+                entries.sort_by_key(|(time, _)| *time);
+                entries
+            }
+            Storage::Managed(_) => panic!("range scan on state created without `ordered`"),
+            Storage::Ordered(map) => map
+                .range(range)
+                .into_iter()
+                .map(|(time, value)| (time, (*value).clone()))
+                .collect(),
+        }
+    }
+
+    /// Removes and returns the entries with times before `bound`, in time order.
+    fn remove_before(&mut self, bound: usize) -> Vec<(usize, V)> {
+        let removed = match &mut self.storage {
+            Storage::Native(map) => {
+                let mut times: Vec<usize> =
+                    map.keys().filter(|time| **time < bound).cloned().collect();
+                times.sort();
+                times
+                    .into_iter()
+                    .map(|time| (time, map.remove(&time).unwrap()))
+                    .collect()
+            }
+            Storage::Managed(_) => panic!("range scan on state created without `ordered`"),
+            Storage::Ordered(map) => map.remove_range(..bound),
+        };
+        for (_, value) in removed.iter() {
+            self.size.removed(1, value);
+        }
+        removed
+    }
+}
+
+/// Snapshot of an operator's open windows, for checkpoints.
+///
+/// `F` identifies what fires at an emission time, `W` identifies a window, and `V` is the state
+/// of one key in a window.
+#[derive(Serialize, Deserialize)]
+struct Snapshot<F, W, K, V> {
+    firings: Vec<(usize, Vec<F>)>,
+    cleanups: Vec<(usize, Vec<W>)>,
+    windows: Vec<(W, Vec<(K, V)>)>,
+}
+
+impl<F, W, K, V> Snapshot<F, W, K, V>
+where
+    F: Clone + Eq + Hash,
+    W: ExchangeData + Ord + Hash + Serialize + DeserializeOwned,
+    K: ExchangeData + Ord + Hash + Serialize + DeserializeOwned,
+    V: 'static + FasterRmw + Clone + Abomonation,
+{
+    /// Captures the windows that are yet to expire, which are all windows holding state.
+    fn capture(
+        firings: &BTreeMap<usize, (Capability<usize>, HashSet<F>)>,
+        cleanups: &BTreeMap<usize, HashSet<W>>,
+        keys: &mut Store<W, Vec<K>>,
+        state: &mut Store<(K, W), V>,
+    ) -> Self {
+        let windows = cleanups
+            .values()
+            .flatten()
+            .map(|window| {
+                let values = keys
+                    .get(window)
+                    .unwrap_or(Vec::new())
+                    .into_iter()
+                    .filter_map(|key| {
+                        let value = state.get(&(key.clone(), window.clone()));
+                        value.map(|value| (key, value))
+                    })
+                    .collect();
+                (window.clone(), values)
+            })
+            .collect();
+
+        Snapshot {
+            firings: firings
+                .iter()
+                .map(|(time, (_, fired))| (*time, fired.iter().cloned().collect()))
+                .collect(),
+            cleanups: cleanups
+                .iter()
+                .map(|(time, windows)| (*time, windows.iter().cloned().collect()))
+                .collect(),
+            windows,
+        }
+    }
+
+    /// Restores the captured windows, with capabilities for their firings from `capability`.
+    fn restore(
+        self,
+        capability: &Capability<usize>,
+        firings: &mut BTreeMap<usize, (Capability<usize>, HashSet<F>)>,
+        cleanups: &mut BTreeMap<usize, HashSet<W>>,
+        keys: &mut Store<W, Vec<K>>,
+        state: &mut Store<(K, W), V>,
+    ) {
+        for (time, fired) in self.firings.into_iter() {
+            let fired = fired.into_iter().collect();
+            firings.insert(time, (capability.delayed(&time), fired));
+        }
+        for (time, windows) in self.cleanups.into_iter() {
+            cleanups.insert(time, windows.into_iter().collect());
+        }
+        for (window, values) in self.windows.into_iter() {
+            keys.insert(
+                window.clone(),
+                values.iter().map(|(key, _)| key.clone()).collect(),
+            );
+            for (key, value) in values.into_iter() {
+                state.insert((key, window.clone()), value);
+            }
+        }
+    }
+}
+
+/// Aggregates each key over tumbling windows of `size_ns`.
+///
+/// Returns the window results and the late data. See the module documentation for lateness.
+pub fn tumbling<S, K, V, A, P>(
+    stream: &Stream<S, (K, Date, V)>,
+    pact: P,
+    name: &str,
+    checkpoints: &Checkpoints,
+    state_sizes: &StateSizes,
+    nt: NexmarkTimer,
+    size_ns: usize,
+    lateness_ns: usize,
+    backing: Backing,
+) -> (Stream<S, (K, Date, A)>, Stream<S, (K, Date, V)>)
+where
+    S: Scope<Timestamp = usize>,
+    K: ExchangeData + Ord + Hash + Serialize + DeserializeOwned,
+    V: ExchangeData,
+    A: Aggregate<V>,
+    P: ParallelizationContract<usize, (K, Date, V)>,
+{
+    let scope = stream.scope();
+    let checkpoints = checkpoints.clone();
+
+    let output = stream.unary_frontier(pact, name, move |capability, info, state_handle| {
+        let operator = checkpoints.register(name, scope.activator_for(&info.address[..]));
+        // Stores (key, window end) -> aggregate
+        let mut aggregates: Store<(K, usize), A> =
+            Store::new(backing, state_sizes, name, "aggregates", |map| {
+                state_handle.get_managed_map(map)
+            });
+        // Stores window end -> keys with an aggregate in that window
+        let mut keys: Store<usize, Vec<K>> =
+            Store::new(backing, state_sizes, name, "keys", |map| {
+                state_handle.get_managed_map(map)
+            });
+        // Stores emission time -> (capability, ends of the windows to emit then)
+        let mut firings: BTreeMap<usize, (Capability<usize>, HashSet<usize>)> = BTreeMap::new();
+        // Stores window expiry -> ends of the windows expiring then
+        let mut cleanups: BTreeMap<usize, HashSet<usize>> = BTreeMap::new();
+        if let Some(snapshot) = checkpoints.restore::<Snapshot<usize, usize, K, A>>(&operator) {
+            snapshot.restore(
+                &capability,
+                &mut firings,
+                &mut cleanups,
+                &mut keys,
+                &mut aggregates,
+            );
+        }
+
+        move |input, output| {
+            input.for_each(|time, data| {
+                // Fold the batch first, so each (key, window) is written once.
+                let mut deltas: HashMap<(K, usize), A> = HashMap::new();
+                let mut late = Vec::new();
+                for (key, date, value) in data.iter().cloned() {
+                    let end_date = window_end(date, size_ns);
+                    let expiry = nt.from_nexmark_time(end_date + Date::new(lateness_ns));
+                    if *time.time() >= expiry {
+                        late.push((key, date, value));
+                        continue;
+                    }
+                    let end = nt.from_nexmark_time(end_date);
+                    let fire = std::cmp::max(end, *time.time() + 1);
+                    firings
+                        .entry(fire)
+                        .or_insert_with(|| (time.delayed(&fire), HashSet::new()))
+                        .1
+                        .insert(end);
+                    cleanups.entry(expiry).or_default().insert(end);
+                    deltas.entry((key, end)).or_default().fold(value);
+                }
+                for (key, delta) in deltas.into_iter() {
+                    if !aggregates.contains_key(&key) {
+                        keys.rmw(key.1, vec![key.0.clone()]);
+                    }
+                    aggregates.rmw(key, delta);
+                }
+                let mut session = output.session(&time);
+                for record in late.into_iter() {
+                    session.give(Err(record));
+                }
+            });
+
+            // A window ends where the next starts, so emit it once the frontier reaches its end.
+            let complete = input
+                .frontier
+                .frontier()
+                .get(0)
+                .cloned()
+                .unwrap_or(usize::max_value());
+            for (_, (capability, ends)) in take_before(&mut firings, complete.saturating_add(1)) {
+                let mut session = output.session(&capability);
+                for end in ends.into_iter() {
+                    for key in keys.get(&end).unwrap_or(Vec::new()) {
+                        if let Some(aggregate) = aggregates.get(&(key.clone(), end)) {
+                            session.give(Ok((key, nt.to_nexmark_time(end), aggregate)));
+                        }
+                    }
+                }
+            }
+            for (_, ends) in take_before(&mut cleanups, complete.saturating_add(1)) {
+                for end in ends.into_iter() {
+                    for key in keys.remove(&end).unwrap_or(Vec::new()) {
+                        aggregates.remove(&(key, end));
+                    }
+                }
+            }
+            if checkpoints.is_due(&operator, complete) {
+                let snapshot = Snapshot::capture(&firings, &cleanups, &mut keys, &mut aggregates);
+                checkpoints.write(&operator, &snapshot);
+            }
+        }
+    });
+
+    split(&output)
+}
+
+/// Aggregates each key over sliding windows of `slice_count` slices of `slide_ns`, one window
+/// ending every `slide_ns`.
+///
+/// Values are aggregated once per slice, and each window merges the aggregates of its slices.
+/// A value is late once all windows containing it have expired.
+pub fn sliding<S, K, V, A, P>(
+    stream: &Stream<S, (K, Date, V)>,
+    pact: P,
+    name: &str,
+    checkpoints: &Checkpoints,
+    state_sizes: &StateSizes,
+    nt: NexmarkTimer,
+    slide_ns: usize,
+    slice_count: usize,
+    lateness_ns: usize,
+    backing: Backing,
+) -> (Stream<S, (K, Date, A)>, Stream<S, (K, Date, V)>)
+where
+    S: Scope<Timestamp = usize>,
+    K: ExchangeData + Ord + Hash + Serialize + DeserializeOwned,
+    V: ExchangeData,
+    A: Aggregate<V>,
+    P: ParallelizationContract<usize, (K, Date, V)>,
+{
+    let scope = stream.scope();
+    let checkpoints = checkpoints.clone();
+
+    let output = stream.unary_frontier(pact, name, move |capability, info, state_handle| {
+        let operator = checkpoints.register(name, scope.activator_for(&info.address[..]));
+        // Stores (key, slice end) -> aggregate
+        let mut aggregates: Store<(K, usize), A> =
+            Store::new(backing, state_sizes, name, "aggregates", |map| {
+                state_handle.get_managed_map(map)
+            });
+        // Stores slice end -> keys with an aggregate in that slice, ordered by slice end and
+        // indexed by the span of a window
+        let mut keys: Store<usize, Vec<K>> = Store::ordered(
+            backing,
+            state_sizes,
+            name,
+            "keys",
+            slide_ns * slice_count,
+            |map| state_handle.get_managed_map(map),
+            |map| state_handle.get_managed_map(map),
+        );
+        // Stores emission time -> (capability, ends of the windows to emit then)
+        let mut firings: BTreeMap<usize, (Capability<usize>, HashSet<usize>)> = BTreeMap::new();
+        // Stores expiry of a slice's last window -> ends of the slices expiring then
+        let mut cleanups: BTreeMap<usize, HashSet<usize>> = BTreeMap::new();
+        if let Some(snapshot) = checkpoints.restore::<Snapshot<usize, usize, K, A>>(&operator) {
+            snapshot.restore(
+                &capability,
+                &mut firings,
+                &mut cleanups,
+                &mut keys,
+                &mut aggregates,
+            );
+        }
+
+        move |input, output| {
+            input.for_each(|time, data| {
+                // Fold the batch first, so each (key, slice) is written once.
+                let mut deltas: HashMap<(K, usize), A> = HashMap::new();
+                let mut late = Vec::new();
+                for (key, date, value) in data.iter().cloned() {
+                    let slice = window_end(date, slide_ns);
+                    // The slice is part of the windows ending at it and at the following slides.
+                    let mut expiry = 0;
+                    for i in 0..slice_count {
+                        let end_date = slice + Date::new(i * slide_ns);
+                        expiry = nt.from_nexmark_time(end_date + Date::new(lateness_ns));
+                        if *time.time() < expiry {
+                            let end = nt.from_nexmark_time(end_date);
+                            let fire = std::cmp::max(end, *time.time() + 1);
+                            firings
+                                .entry(fire)
+                                .or_insert_with(|| (time.delayed(&fire), HashSet::new()))
+                                .1
+                                .insert(end);
+                        }
+                    }
+                    if *time.time() >= expiry {
+                        late.push((key, date, value));
+                        continue;
+                    }
+                    let slice = nt.from_nexmark_time(slice);
+                    cleanups.entry(expiry).or_default().insert(slice);
+                    deltas.entry((key, slice)).or_default().fold(value);
+                }
+                for (key, delta) in deltas.into_iter() {
+                    if !aggregates.contains_key(&key) {
+                        keys.rmw(key.1, vec![key.0.clone()]);
+                    }
+                    aggregates.rmw(key, delta);
+                }
+                let mut session = output.session(&time);
+                for record in late.into_iter() {
+                    session.give(Err(record));
+                }
+            });
+
+            let complete = input
+                .frontier
+                .frontier()
+                .get(0)
+                .cloned()
+                .unwrap_or(usize::max_value());
+            for (_, (capability, ends)) in take_before(&mut firings, complete.saturating_add(1)) {
+                let mut session = output.session(&capability);
+                for end in ends.into_iter() {
+                    let end_date = nt.to_nexmark_time(end);
+                    let first = Date::new(end_date.saturating_sub((slice_count - 1) * slide_ns));
+                    let first = nt.from_nexmark_time(first);
+
+                    // Merge the aggregates of the slices holding state, skipping empty ones.
+                    let mut window = HashMap::new();
+                    for (slice, slice_keys) in keys.range(first..=end) {
+                        for key in slice_keys.into_iter() {
+                            if let Some(slice_aggregate) = aggregates.get(&(key.clone(), slice)) {
+                                merge_into(&mut window, key, slice_aggregate);
+                            }
+                        }
+                    }
+
+                    for (key, aggregate) in window.into_iter() {
+                        session.give(Ok((key, end_date, aggregate)));
+                    }
+                }
+            }
+            let expired = take_before(&mut cleanups, complete.saturating_add(1));
+            if let Some(last) = expired.values().flatten().max() {
+                // Slices expire in order, so this also removes any slice left behind.
+                for (slice, slice_keys) in keys.remove_before(last + 1) {
+                    for key in slice_keys.into_iter() {
+                        aggregates.remove(&(key, slice));
+                    }
+                }
+            }
+            if checkpoints.is_due(&operator, complete) {
+                let snapshot = Snapshot::capture(&firings, &cleanups, &mut keys, &mut aggregates);
+                checkpoints.write(&operator, &snapshot);
+            }
+        }
+    });
+
+    split(&output)
+}
+
+/// An open session of a key.
+#[derive(Clone, Serialize, Deserialize, Abomonation)]
+struct Session<A> {
+    start: Date,
+    last: Date,
+    aggregate: A,
+}
+
+impl<A: FasterRmw + Clone> FasterRmw for Session<A> {
+    fn rmw(&self, _modification: Self) -> Self {
+        panic!("RMW on Session not allowed!");
+    }
+}
+
+/// Snapshot of the open sessions of a `session` operator, for checkpoints.
+#[derive(Serialize, Deserialize)]
+struct SessionSnapshot<K, A> {
+    closings: Vec<(usize, Vec<K>)>,
+    sessions: Vec<(K, Session<A>)>,
+}
+
+/// Aggregates each key over sessions, which close once the key sees no value for `gap_ns`.
+///
+/// The emitted window end is the session's closing time, its last value plus the gap. Sessions
+/// have no late data: a value after its session closed starts a new one. Each key has one open
+/// session, so a value more than the gap before the start of the open session is emitted as a
+/// session of its own right away.
+pub fn session<S, K, V, A, P>(
+    stream: &Stream<S, (K, Date, V)>,
+    pact: P,
+    name: &str,
+    checkpoints: &Checkpoints,
+    state_sizes: &StateSizes,
+    nt: NexmarkTimer,
+    gap_ns: usize,
+    backing: Backing,
+) -> Stream<S, (K, Date, A)>
+where
+    S: Scope<Timestamp = usize>,
+    K: ExchangeData + Ord + Hash + Serialize + DeserializeOwned,
+    V: ExchangeData,
+    A: Aggregate<V>,
+    P: ParallelizationContract<usize, (K, Date, V)>,
+{
+    let gap = Date::new(gap_ns);
+    let scope = stream.scope();
+    let checkpoints = checkpoints.clone();
+
+    stream.unary_frontier(pact, name, move |capability, info, state_handle| {
+        let operator = checkpoints.register(name, scope.activator_for(&info.address[..]));
+        // Stores key -> open session
+        let mut sessions: Store<K, Session<A>> =
+            Store::new(backing, state_sizes, name, "sessions", |map| {
+                state_handle.get_managed_map(map)
+            });
+        // Stores closing time -> keys whose session may close then
+        let mut closings: Store<usize, Vec<K>> =
+            Store::new(backing, state_sizes, name, "closings", |map| {
+                state_handle.get_managed_map(map)
+            });
+        let mut capabilities = BTreeMap::new();
+        if let Some(snapshot) = checkpoints.restore::<SessionSnapshot<K, A>>(&operator) {
+            for (close, keys) in snapshot.closings.into_iter() {
+                capabilities.insert(close, capability.delayed(&close));
+                closings.insert(close, keys);
+            }
+            for (key, open) in snapshot.sessions.into_iter() {
+                sessions.insert(key, open);
+            }
+        }
+
+        move |input, output| {
+            input.for_each(|time, data| {
+                let mut output_session = output.session(&time);
+                for (key, date, value) in data.iter().cloned() {
+                    let open = match sessions.remove(&key) {
+                        Some(ref open) if open.last + gap < date => {
+                            // The previous session ended before this value, close it now.
+                            output_session.give((
+                                key.clone(),
+                                open.last + gap,
+                                open.aggregate.clone(),
+                            ));
+                            None
+                        }
+                        Some(open) => {
+                            if date + gap < open.start {
+                                // The value belongs to an earlier session that already closed.
+                                let mut earlier = A::default();
+                                earlier.fold(value);
+                                output_session.give((key.clone(), date + gap, earlier));
+                                sessions.insert(key, open);
+                                continue;
+                            }
+                            Some(open)
+                        }
+                        None => None,
+                    };
+                    let mut open = open.unwrap_or(Session {
+                        start: date,
+                        last: date,
+                        aggregate: A::default(),
+                    });
+                    open.start = std::cmp::min(open.start, date);
+                    open.last = std::cmp::max(open.last, date);
+                    open.aggregate.fold(value);
+
+                    // Out-of-order values may close a session before the current time.
+                    let close = std::cmp::max(nt.from_nexmark_time(open.last + gap), *time.time());
+                    capabilities
+                        .entry(close)
+                        .or_insert_with(|| time.delayed(&close));
+                    closings.rmw(close, vec![key.clone()]);
+                    sessions.insert(key, open);
+                }
+            });
+
+            // A value at the closing time extends the session, so wait for the frontier to pass it.
+            let complete = input
+                .frontier
+                .frontier()
+                .get(0)
+                .cloned()
+                .unwrap_or(usize::max_value());
+            for (close, capability) in take_before(&mut capabilities, complete) {
+                let mut output_session = output.session(&capability);
+                for key in closings.remove(&close).unwrap_or(Vec::new()) {
+                    // Sessions extended since this closing was registered stay open.
+                    let expired = sessions.get(&key).map_or(false, |open| {
+                        std::cmp::max(nt.from_nexmark_time(open.last + gap), close) == close
+                    });
+                    if expired {
+                        let open = sessions.remove(&key).expect("Must exist");
+                        output_session.give((key, open.last + gap, open.aggregate));
+                    }
+                }
+            }
+            if checkpoints.is_due(&operator, complete) {
+                let mut snapshot = SessionSnapshot {
+                    closings: Vec::new(),
+                    sessions: Vec::new(),
+                };
+                let mut captured = HashSet::new();
+                for close in capabilities.keys() {
+                    let keys = closings.get(close).unwrap_or(Vec::new());
+                    for key in keys.iter() {
+                        if captured.insert(key.clone()) {
+                            if let Some(open) = sessions.get(key) {
+                                snapshot.sessions.push((key.clone(), open));
+                            }
+                        }
+                    }
+                    snapshot.closings.push((*close, keys));
+                }
+                checkpoints.write(&operator, &snapshot);
+            }
+        }
+    })
+}
+
+/// Aggregates each key over tumbling windows of `size_ns` on the worker the values are on, and
+/// forwards the partial aggregate of each window once the frontier reaches its end, dated at the
+/// window's start, to be exchanged to a window operator that merges the partial aggregates.
+///
+/// The partial aggregates are forwarded at the time just before the window's end, so the
+/// following operator doesn't take them for late data. Forwarded windows are dropped, so each
+/// value is forwarded once. A value whose window was already forwarded is forwarded on its own
+/// right away, with its date, for the following operator to aggregate or report as late.
+pub fn pre_aggregate<S, K, V, A>(
+    stream: &Stream<S, (K, Date, V)>,
+    name: &str,
+    checkpoints: &Checkpoints,
+    state_sizes: &StateSizes,
+    nt: NexmarkTimer,
+    size_ns: usize,
+    backing: Backing,
+) -> Stream<S, (K, Date, A)>
+where
+    S: Scope<Timestamp = usize>,
+    K: ExchangeData + Ord + Hash + Serialize + DeserializeOwned,
+    V: ExchangeData,
+    A: Aggregate<V>,
+{
+    let scope = stream.scope();
+    let checkpoints = checkpoints.clone();
+
+    stream.unary_frontier(Pipeline, name, move |capability, info, state_handle| {
+        let operator = checkpoints.register(name, scope.activator_for(&info.address[..]));
+        // Stores (key, window end) -> partial aggregate
+        let mut aggregates: Store<(K, usize), A> =
+            Store::new(backing, state_sizes, name, "aggregates", |map| {
+                state_handle.get_managed_map(map)
+            });
+        // Stores window end -> keys with a partial aggregate in that window
+        let mut keys: Store<usize, Vec<K>> =
+            Store::new(backing, state_sizes, name, "keys", |map| {
+                state_handle.get_managed_map(map)
+            });
+        // Stores forwarding time -> (capability, ends of the windows to forward then)
+        let mut firings: BTreeMap<usize, (Capability<usize>, HashSet<usize>)> = BTreeMap::new();
+        // Stores window end -> the window, for the snapshots of the windows holding state
+        let mut open: BTreeMap<usize, HashSet<usize>> = BTreeMap::new();
+        if let Some(snapshot) = checkpoints.restore::<Snapshot<usize, usize, K, A>>(&operator) {
+            snapshot.restore(
+                &capability,
+                &mut firings,
+                &mut open,
+                &mut keys,
+                &mut aggregates,
+            );
+        }
+
+        move |input, output| {
+            input.for_each(|time, data| {
+                // Fold the batch first, so each (key, window) is written once.
+                let mut deltas: HashMap<(K, usize), A> = HashMap::new();
+                let mut forwarded = Vec::new();
+                for (key, date, value) in data.iter().cloned() {
+                    let end = nt.from_nexmark_time(window_end(date, size_ns));
+                    if *time.time() >= end {
+                        let mut partial = A::default();
+                        partial.fold(value);
+                        forwarded.push((key, date, partial));
+                        continue;
+                    }
+                    firings
+                        .entry(end - 1)
+                        .or_insert_with(|| (time.delayed(&(end - 1)), HashSet::new()))
+                        .1
+                        .insert(end);
+                    open.entry(end).or_default().insert(end);
+                    deltas.entry((key, end)).or_default().fold(value);
+                }
+                for (key, delta) in deltas.into_iter() {
+                    if !aggregates.contains_key(&key) {
+                        keys.rmw(key.1, vec![key.0.clone()]);
+                    }
+                    aggregates.rmw(key, delta);
+                }
+                output.session(&time).give_iterator(forwarded.into_iter());
+            });
+
+            // No more values fall in a window once the frontier reaches its end.
+            let complete = input
+                .frontier
+                .frontier()
+                .get(0)
+                .cloned()
+                .unwrap_or(usize::max_value());
+            for (_, (capability, ends)) in take_before(&mut firings, complete) {
+                let mut session = output.session(&capability);
+                for end in ends.into_iter() {
+                    open.remove(&end);
+                    let start = Date::new(nt.to_nexmark_time(end).saturating_sub(size_ns));
+                    for key in keys.remove(&end).unwrap_or(Vec::new()) {
+                        if let Some(aggregate) = aggregates.remove(&(key.clone(), end)) {
+                            session.give((key, start, aggregate));
+                        }
+                    }
+                }
+            }
+            if checkpoints.is_due(&operator, complete) {
+                let snapshot = Snapshot::capture(&firings, &open, &mut keys, &mut aggregates);
+                checkpoints.write(&operator, &snapshot);
+            }
+        }
+    })
+}
+
+/// Merges partial aggregates of the same key and window, such as those of `tumbling` windows
+/// aggregated on each worker before an exchange.
+///
+/// Partial aggregates arrive at the time their window fired, and the merged aggregate is emitted
+/// once the frontier passes it. Windows are kept until `lateness_ns` after their end, so that
+/// re-fired partial aggregates merge with the earlier ones. Re-fired partial aggregates include
+/// the earlier values, so only an `IdempotentAggregate`, such as `Max`, can be combined.
+pub fn combine<S, K, A, P>(
+    stream: &Stream<S, (K, Date, A)>,
+    pact: P,
+    name: &str,
+    checkpoints: &Checkpoints,
+    state_sizes: &StateSizes,
+    nt: NexmarkTimer,
+    lateness_ns: usize,
+    backing: Backing,
+) -> Stream<S, (K, Date, A)>
+where
+    S: Scope<Timestamp = usize>,
+    K: ExchangeData + Ord + Hash + Serialize + DeserializeOwned,
+    A: IdempotentAggregate + ExchangeData,
+    P: ParallelizationContract<usize, (K, Date, A)>,
+{
+    let scope = stream.scope();
+    let checkpoints = checkpoints.clone();
+
+    stream.unary_frontier(pact, name, move |capability, info, state_handle| {
+        let operator = checkpoints.register(name, scope.activator_for(&info.address[..]));
+        // Stores (key, window end) -> aggregate
+        let mut aggregates: Store<(K, Date), A> =
+            Store::new(backing, state_sizes, name, "aggregates", |map| {
+                state_handle.get_managed_map(map)
+            });
+        // Stores window end -> keys with an aggregate in that window
+        let mut keys: Store<Date, Vec<K>> = Store::new(backing, state_sizes, name, "keys", |map| {
+            state_handle.get_managed_map(map)
+        });
+        // Stores emission time -> (capability, (key, window end) pairs to emit then)
+        let mut firings: BTreeMap<usize, (Capability<usize>, HashSet<(K, Date)>)> = BTreeMap::new();
+        // Stores window expiry -> ends of the windows expiring then
+        let mut cleanups: BTreeMap<usize, HashSet<Date>> = BTreeMap::new();
+        if let Some(snapshot) = checkpoints.restore::<Snapshot<(K, Date), Date, K, A>>(&operator) {
+            snapshot.restore(
+                &capability,
+                &mut firings,
+                &mut cleanups,
+                &mut keys,
+                &mut aggregates,
+            );
+        }
+
+        move |input, output| {
+            input.for_each(|time, data| {
+                // Merge the batch first, so each (key, window) is written once.
+                let mut deltas: HashMap<(K, Date), A> = HashMap::new();
+                for (key, end, aggregate) in data.iter().cloned() {
+                    merge_into(&mut deltas, (key, end), aggregate);
+                }
+                let firing = &mut firings
+                    .entry(*time.time())
+                    .or_insert_with(|| (time.delayed(time.time()), HashSet::new()))
+                    .1;
+                for (key, delta) in deltas.into_iter() {
+                    if !aggregates.contains_key(&key) {
+                        keys.rmw(key.1, vec![key.0.clone()]);
+                        cleanups
+                            .entry(nt.from_nexmark_time(key.1 + Date::new(lateness_ns)))
+                            .or_default()
+                            .insert(key.1);
+                    }
+                    firing.insert(key.clone());
+                    aggregates.rmw(key, delta);
+                }
+            });
+
+            let complete = input
+                .frontier
+                .frontier()
+                .get(0)
+                .cloned()
+                .unwrap_or(usize::max_value());
+            for (_, (capability, windows)) in take_before(&mut firings, complete) {
+                let mut session = output.session(&capability);
+                for key in windows.into_iter() {
+                    if let Some(aggregate) = aggregates.get(&key) {
+                        session.give((key.0, key.1, aggregate));
+                    }
+                }
+            }
+            // Partial aggregates arrive until a window expires, so wait for the frontier to pass.
+            for (_, ends) in take_before(&mut cleanups, complete) {
+                for end in ends.into_iter() {
+                    for key in keys.remove(&end).unwrap_or(Vec::new()) {
+                        aggregates.remove(&(key, end));
+                    }
+                }
+            }
+            if checkpoints.is_due(&operator, complete) {
+                let snapshot = Snapshot::capture(&firings, &cleanups, &mut keys, &mut aggregates);
+                checkpoints.write(&operator, &snapshot);
+            }
+        }
+    })
+}
+
+/// The values of both join inputs for one key and window, and how many of each were joined in
+/// earlier emissions of the window.
+#[derive(Clone, Serialize, Deserialize, Abomonation)]
+struct JoinBuffers<L, R> {
+    left: Vec<L>,
+    right: Vec<R>,
+    fired_left: usize,
+    fired_right: usize,
+}
+
+impl<L, R> Default for JoinBuffers<L, R> {
+    fn default() -> Self {
+        JoinBuffers {
+            left: Vec::new(),
+            right: Vec::new(),
+            fired_left: 0,
+            fired_right: 0,
+        }
+    }
+}
+
+impl<L, R> FasterRmw for JoinBuffers<L, R>
+where
+    L: Clone + Serialize + DeserializeOwned,
+    R: Clone + Serialize + DeserializeOwned,
+{
+    fn rmw(&self, modification: Self) -> Self {
+        let mut merged = self.clone();
+        merged.left.extend(modification.left);
+        merged.right.extend(modification.right);
+        merged
+    }
+}
+
+/// Output of `tumbling_join`, before it is split into joined pairs and late data.
+#[derive(Clone)]
+enum JoinOutput<K, L, R> {
+    Joined((K, Date, L, R)),
+    LateLeft((K, Date, L)),
+    LateRight((K, Date, R)),
+}
+
+/// Joins two streams on key within tumbling windows of `size_ns`, emitting every pair of values
+/// with the same key in the same window once the window closes.
+///
+/// Returns the joined pairs and the late data of each input. A window re-fires only with the
+/// pairs involving values that arrived since it last fired.
+pub fn tumbling_join<S, K, L, R, P1, P2>(
+    left: &Stream<S, (K, Date, L)>,
+    right: &Stream<S, (K, Date, R)>,
+    pact1: P1,
+    pact2: P2,
+    name: &str,
+    checkpoints: &Checkpoints,
+    state_sizes: &StateSizes,
+    nt: NexmarkTimer,
+    size_ns: usize,
+    lateness_ns: usize,
+    backing: Backing,
+) -> (
+    Stream<S, (K, Date, L, R)>,
+    Stream<S, (K, Date, L)>,
+    Stream<S, (K, Date, R)>,
+)
+where
+    S: Scope<Timestamp = usize>,
+    K: ExchangeData + Ord + Hash + Serialize + DeserializeOwned,
+    L: ExchangeData + Serialize + DeserializeOwned,
+    R: ExchangeData + Serialize + DeserializeOwned,
+    P1: ParallelizationContract<usize, (K, Date, L)>,
+    P2: ParallelizationContract<usize, (K, Date, R)>,
+{
+    let scope = left.scope();
+    let checkpoints = checkpoints.clone();
+
+    let output = left.binary_frontier(
+        right,
+        pact1,
+        pact2,
+        name,
+        move |capability, info, state_handle| {
+            let operator = checkpoints.register(name, scope.activator_for(&info.address[..]));
+            // Stores (key, window end) -> values of both inputs
+            let mut buffers: Store<(K, usize), JoinBuffers<L, R>> =
+                Store::new(backing, state_sizes, name, "buffers", |map| {
+                    state_handle.get_managed_map(map)
+                });
+            // Stores window end -> keys with values in that window
+            let mut keys: Store<usize, Vec<K>> =
+                Store::new(backing, state_sizes, name, "keys", |map| {
+                    state_handle.get_managed_map(map)
+                });
+            // Stores emission time -> (capability, ends of the windows to emit then)
+            let mut firings: BTreeMap<usize, (Capability<usize>, HashSet<usize>)> = BTreeMap::new();
+            // Stores window expiry -> ends of the windows expiring then
+            let mut cleanups: BTreeMap<usize, HashSet<usize>> = BTreeMap::new();
+            if let Some(snapshot) =
+                checkpoints.restore::<Snapshot<usize, usize, K, JoinBuffers<L, R>>>(&operator)
+            {
+                snapshot.restore(
+                    &capability,
+                    &mut firings,
+                    &mut cleanups,
+                    &mut keys,
+                    &mut buffers,
+                );
+            }
+
+            move |input1, input2, output| {
+                // Collect both inputs first, so each (key, window) is written once.
+                let mut deltas: HashMap<(K, usize), JoinBuffers<L, R>> = HashMap::new();
+                input1.for_each(|time, data| {
+                    let mut session = output.session(&time);
+                    for (key, date, value) in data.iter().cloned() {
+                        let end_date = window_end(date, size_ns);
+                        let expiry = nt.from_nexmark_time(end_date + Date::new(lateness_ns));
+                        if *time.time() >= expiry {
+                            session.give(JoinOutput::LateLeft((key, date, value)));
+                            continue;
+                        }
+                        let end = nt.from_nexmark_time(end_date);
+                        let fire = std::cmp::max(end, *time.time() + 1);
+                        firings
+                            .entry(fire)
+                            .or_insert_with(|| (time.delayed(&fire), HashSet::new()))
+                            .1
+                            .insert(end);
+                        cleanups.entry(expiry).or_default().insert(end);
+                        deltas.entry((key, end)).or_default().left.push(value);
+                    }
+                });
+                input2.for_each(|time, data| {
+                    let mut session = output.session(&time);
+                    for (key, date, value) in data.iter().cloned() {
+                        let end_date = window_end(date, size_ns);
+                        let expiry = nt.from_nexmark_time(end_date + Date::new(lateness_ns));
+                        if *time.time() >= expiry {
+                            session.give(JoinOutput::LateRight((key, date, value)));
+                            continue;
+                        }
+                        let end = nt.from_nexmark_time(end_date);
+                        let fire = std::cmp::max(end, *time.time() + 1);
+                        firings
+                            .entry(fire)
+                            .or_insert_with(|| (time.delayed(&fire), HashSet::new()))
+                            .1
+                            .insert(end);
+                        cleanups.entry(expiry).or_default().insert(end);
+                        deltas.entry((key, end)).or_default().right.push(value);
+                    }
+                });
+                for (key, delta) in deltas.into_iter() {
+                    if !buffers.contains_key(&key) {
+                        keys.rmw(key.1, vec![key.0.clone()]);
+                    }
+                    buffers.rmw(key, delta);
+                }
+
+                let complete1 = input1
+                    .frontier
+                    .frontier()
+                    .get(0)
+                    .cloned()
+                    .unwrap_or(usize::max_value());
+                let complete2 = input2
+                    .frontier
+                    .frontier()
+                    .get(0)
+                    .cloned()
+                    .unwrap_or(usize::max_value());
+                let complete = std::cmp::min(complete1, complete2);
+                for (_, (capability, ends)) in take_before(&mut firings, complete.saturating_add(1))
+                {
+                    let mut session = output.session(&capability);
+                    for end in ends.into_iter() {
+                        for key in keys.get(&end).unwrap_or(Vec::new()) {
+                            let mut buffer = match buffers.remove(&(key.clone(), end)) {
+                                Some(buffer) => buffer,
+                                None => continue,
+                            };
+                            // Skip the pairs emitted when the window fired before.
+                            for (i, left) in buffer.left.iter().enumerate() {
+                                for (j, right) in buffer.right.iter().enumerate() {
+                                    if i >= buffer.fired_left || j >= buffer.fired_right {
+                                        session.give(JoinOutput::Joined((
+                                            key.clone(),
+                                            nt.to_nexmark_time(end),
+                                            left.clone(),
+                                            right.clone(),
+                                        )));
+                                    }
+                                }
+                            }
+                            if lateness_ns > 0 {
+                                buffer.fired_left = buffer.left.len();
+                                buffer.fired_right = buffer.right.len();
+                                buffers.insert((key, end), buffer);
+                            }
+                        }
+                    }
+                }
+                for (_, ends) in take_before(&mut cleanups, complete.saturating_add(1)) {
+                    for end in ends.into_iter() {
+                        for key in keys.remove(&end).unwrap_or(Vec::new()) {
+                            buffers.remove(&(key, end));
+                        }
+                    }
+                }
+                if checkpoints.is_due(&operator, complete) {
+                    let snapshot = Snapshot::capture(&firings, &cleanups, &mut keys, &mut buffers);
+                    checkpoints.write(&operator, &snapshot);
+                }
+            }
+        },
+    );
+
+    (
+        output.flat_map(|x| match x {
+            JoinOutput::Joined(joined) => Some(joined),
+            _ => None,
+        }),
+        output.flat_map(|x| match x {
+            JoinOutput::LateLeft(late) => Some(late),
+            _ => None,
+        }),
+        output.flat_map(|x| match x {
+            JoinOutput::LateRight(late) => Some(late),
+            _ => None,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{take_before, window_end};
+    use crate::event::Date;
+
+    #[test]
+    fn window_end_is_the_end_of_the_window_holding_the_date() {
+        assert_eq!(window_end(Date::new(0), 10), Date::new(10));
+        assert_eq!(window_end(Date::new(9), 10), Date::new(10));
+        assert_eq!(window_end(Date::new(10), 10), Date::new(20));
+    }
+
+    #[test]
+    fn take_before_removes_the_entries_before_the_bound() {
+        let mut entries: BTreeMap<usize, &str> =
+            vec![(1, "a"), (5, "b"), (9, "c")].into_iter().collect();
+        let taken = take_before(&mut entries, 5);
+        assert_eq!(taken.into_iter().collect::<Vec<_>>(), vec![(1, "a")]);
+        assert_eq!(
+            entries.into_iter().collect::<Vec<_>>(),
+            vec![(5, "b"), (9, "c")]
+        );
+        let mut entries: BTreeMap<usize, &str> = vec![(1, "a")].into_iter().collect();
+        assert!(take_before(&mut entries, 0).is_empty());
+        assert_eq!(entries.len(), 1);
+    }
+}